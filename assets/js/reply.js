@@ -17,6 +17,24 @@ document.addEventListener("DOMContentLoaded", () => {
   let loading = false;
   let formData = new FormData();
 
+  // the options of every file, keyed by the file name so they can't get out of order
+  function buildManifest(files) {
+    const manifest = {};
+
+    files.forEach((file, idx) => {
+      manifest[file.name] = {
+        "last-mod": getDate(file.lastModified, false),
+        size: file.size,
+        "cut-row": Number(cutRows[idx]) || 0,
+        rename: rename[idx] ?? false,
+        checked: checked[idx] ?? false,
+        reply: reply[idx] ?? false,
+      };
+    });
+
+    return JSON.stringify(manifest);
+  }
+
   function updateTotalCount() {
     let total_count = document.getElementById("total-count");
     total_count.textContent = `Total ${excelList.children.length} files`;
@@ -239,58 +257,8 @@ document.addEventListener("DOMContentLoaded", () => {
 
     console.log(formData.getAll("excel-file[]"));
 
-    formData.delete("last-mod[]");
-    // append last mod
-    formData
-      .getAll("excel-file[]")
-      .map((file) => {
-        let lastMod = file.lastModified;
-        const date = getDate(lastMod, false);
-
-        return date;
-      })
-      .forEach((date) => {
-        formData.append("last-mod[]", date);
-      });
-
-    // append sizes
-    formData
-      .getAll("excel-file[]")
-      .map((file) => {
-        let size = file.size;
-        return size;
-      })
-      .forEach((size) => {
-        formData.append("size[]", size);
-      });
-
-    // append cut row
-    formData.getAll("excel-file[]").forEach((_, idx) => {
-      if (cutRows[idx] != undefined) {
-        formData.append("cut-row[]", cutRows[idx]);
-      } else {
-        formData.append("cut-row[]", 0);
-      }
-
-      if (rename[idx] != undefined) {
-        formData.append("rename[]", rename[idx]);
-      } else {
-        formData.append("rename[]", false);
-      }
-
-      if (checked[idx] != undefined) {
-        formData.append("checked[]", checked[idx]);
-      } else {
-        formData.append("checked[]", false);
-      }
+    formData.set("manifest", buildManifest(formData.getAll("excel-file[]")));
 
-      if (reply[idx] != undefined) {
-        formData.append("reply[]", reply[idx]);
-      } else {
-        console.log("pushing false");
-        formData.append("reply[]", false);
-      }
-    });
     console.log(formData);
 
     console.log(formData.getAll("excel-file[]"));
@@ -348,50 +316,10 @@ document.addEventListener("DOMContentLoaded", () => {
 
     let templateFormData = formData;
 
-    templateFormData.delete("last-mod[]");
-    templateFormData.delete("size[]");
-
-    // append last mod
-    templateFormData
-      .getAll("excel-file[]")
-      .map((file) => {
-        let lastMod = file.lastModified;
-        const date = getDate(lastMod, false);
-
-        return date;
-      })
-      .forEach((date) => {
-        templateFormData.append("last-mod[]", date);
-      });
-
-    // append sizes
-    templateFormData
-      .getAll("excel-file[]")
-      .map((file) => {
-        let size = file.size;
-        return size;
-      })
-      .forEach((size) => {
-        templateFormData.append("size[]", size);
-      });
-
-    templateFormData.delete("cut-row[]");
-    templateFormData.delete("checked[]");
-
-    // append cut row
-    templateFormData.getAll("excel-file[]").forEach((_, idx) => {
-      if (cutRows[idx] != undefined) {
-        templateFormData.append("cut-row[]", cutRows[idx]);
-      } else {
-        templateFormData.append("cut-row[]", 0);
-      }
-
-      if (checked[idx] != undefined) {
-        templateFormData.append("checked[]", checked[idx]);
-      } else {
-        templateFormData.append("checked[]", false);
-      }
-    });
+    templateFormData.set(
+      "manifest",
+      buildManifest(templateFormData.getAll("excel-file[]"))
+    );
 
     const res = await fetch("/api/reply-template", {
       method: "POST",
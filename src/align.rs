@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::Cell;
+use crate::error::Result;
+use crate::mapping::header_key;
+
+/// what a column is matched on, the text of its header and which occurrence of that text it is,
+/// so two "Notes" columns in the same file stay two columns
+type ColumnKey = (String, usize);
+
+/// How a file's columns differ from the main file's
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnChange {
+    /// the main file has the column but this file doesn't
+    Missing,
+    /// this file has a column the main file doesn't
+    Added,
+}
+
+/// A column a file lacked or added, for the report sheet
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnDifference {
+    pub file: String,
+    pub column: String,
+    pub change: ColumnChange,
+}
+
+/// The union of the headers of several files, with where each file's columns go in it
+#[derive(Debug, Default)]
+pub struct Alignment {
+    pub header: Vec<Cell>,
+    /// for every file, the position in `header` of each of its columns
+    pub positions: Vec<Vec<usize>>,
+    pub differences: Vec<ColumnDifference>,
+}
+
+impl Alignment {
+    /// line up the headers of the files by name. The columns of the first file come first and in
+    /// its order, the ones it doesn't have follow in the order they're first seen in
+    pub fn new(headers: &[(&str, &[Cell])]) -> Self {
+        let mut alignment = Alignment::default();
+        let mut union: HashMap<ColumnKey, usize> = HashMap::new();
+
+        for (_, header) in headers {
+            let positions = column_keys(header)
+                .into_iter()
+                .zip(header.iter())
+                .map(|(key, cell)| {
+                    *union.entry(key).or_insert_with(|| {
+                        alignment.header.push(cell.clone());
+                        alignment.header.len() - 1
+                    })
+                })
+                .collect();
+
+            alignment.positions.push(positions);
+        }
+
+        let Some((_, main_header)) = headers.first() else {
+            return alignment;
+        };
+        let main_keys = column_keys(main_header);
+
+        for (file, header) in &headers[1..] {
+            let keys = column_keys(header);
+
+            let missing = main_keys
+                .iter()
+                .zip(main_header.iter())
+                .filter(|(key, _)| !keys.contains(key))
+                .map(|(_, cell)| (cell, ColumnChange::Missing));
+            let added = keys
+                .iter()
+                .zip(header.iter())
+                .filter(|(key, _)| !main_keys.contains(key))
+                .map(|(_, cell)| (cell, ColumnChange::Added));
+
+            // columns without a header are lined up, but there's nothing to name them by
+            for (cell, change) in missing.chain(added) {
+                let column = header_name(cell);
+                if column.is_empty() {
+                    continue;
+                }
+
+                alignment.differences.push(ColumnDifference {
+                    file: file.to_string(),
+                    column,
+                    change,
+                });
+            }
+        }
+
+        alignment
+    }
+
+    /// move the cells of a row of the `i`th file to where their columns are in the union
+    pub fn realign(&self, i: usize, row: Vec<Cell>) -> Vec<Cell> {
+        let mut aligned = vec![Cell::Empty; self.header.len()];
+
+        for (cell, position) in row.into_iter().zip(&self.positions[i]) {
+            aligned[*position] = cell;
+        }
+
+        aligned
+    }
+}
+
+/// headers are matched the way a mapping matches them, see [`header_key`]
+fn column_keys(header: &[Cell]) -> Vec<ColumnKey> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    header
+        .iter()
+        .map(|cell| {
+            let name = header_key(&cell.as_text());
+
+            let occurrence = seen.entry(name.clone()).or_insert(0);
+            *occurrence += 1;
+
+            (name, *occurrence)
+        })
+        .collect()
+}
+
+/// the trimmed text of a header the report names its column by, without the line breaks wrapped
+/// headers have
+fn header_name(cell: &Cell) -> String {
+    let name: String = cell
+        .as_text()
+        .chars()
+        .filter(|c| *c != '\n' && *c != '\r')
+        .collect();
+
+    name.trim().to_string()
+}
+
+/// list which file lacked or added which column on a sheet of their own
+pub fn write_column_report(
+    workbook: &mut Workbook,
+    differences: &[ColumnDifference],
+) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Columns")
+        .context("error setting name of columns sheet")?;
+
+    for (j, header) in ["File Name", "Column", "Change"].iter().enumerate() {
+        worksheet
+            .write_string(0, j as u16, *header)
+            .context("error writing header")?;
+    }
+
+    for (i, difference) in differences.iter().enumerate() {
+        let row = (i + 1) as u32;
+        let change = match difference.change {
+            ColumnChange::Missing => "Missing",
+            ColumnChange::Added => "Added",
+        };
+
+        for (j, text) in [difference.file.as_str(), &difference.column, change]
+            .iter()
+            .enumerate()
+        {
+            worksheet
+                .write_string(row, j as u16, *text)
+                .context("error writing column difference")?;
+        }
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_headers() {
+        let main: Vec<Cell> = vec!["Name".into(), "City".into(), "Qty".into()];
+        let other: Vec<Cell> = vec![" CITY".into(), "name".into(), "Notes".into()];
+
+        let alignment = Alignment::new(&[("a.xlsx", &main), ("b.xlsx", &other)]);
+
+        assert_eq!(
+            alignment.header,
+            vec!["Name".into(), "City".into(), "Qty".into(), "Notes".into()]
+        );
+        assert_eq!(
+            alignment.realign(1, vec!["Cairo".into(), "Ali".into(), "new".into()]),
+            vec!["Ali".into(), "Cairo".into(), Cell::Empty, "new".into()]
+        );
+        assert_eq!(
+            alignment.differences,
+            vec![
+                ColumnDifference {
+                    file: "b.xlsx".to_string(),
+                    column: "Qty".to_string(),
+                    change: ColumnChange::Missing,
+                },
+                ColumnDifference {
+                    file: "b.xlsx".to_string(),
+                    column: "Notes".to_string(),
+                    change: ColumnChange::Added,
+                },
+            ]
+        );
+    }
+}
@@ -1,4 +1,8 @@
 #[derive(utoipa::OpenApi)]
-#[openapi(paths(crate::routes::merge::merge_files, crate::routes::search::search_files))]
+#[openapi(paths(
+    crate::routes::merge::merge_files,
+    crate::routes::join::join_files,
+    crate::routes::search::search_files
+))]
 pub struct ApiDoc;
 
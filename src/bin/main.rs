@@ -1,7 +1,7 @@
 use anyhow::Context;
 use axum::response::IntoResponse;
 use axum::{
-    extract::DefaultBodyLimit,
+    extract::{DefaultBodyLimit, Extension},
     http::{
         header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LAST_MODIFIED},
         HeaderValue, Method,
@@ -14,6 +14,7 @@ use axum::{
 use excel_merge::api::ApiDoc;
 use excel_merge::error::{self, Result};
 use excel_merge::routes::{self};
+use excel_merge::upload::UploadLimits;
 use std::net::SocketAddr;
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
@@ -45,12 +46,16 @@ async fn main() -> error::Result<()> {
 
     info!("using version: {:?}", VERSION.unwrap_or("unkown"));
 
+    let limits = UploadLimits::from_env();
+    info!("upload limits: {:?}", limits);
+
     // serve static files
     let serve_dir = ServeDir::new("assets").not_found_service(ServeFile::new("assets/index.html"));
 
     let router = Router::new()
         // TODO: make a seperate router for api
         .route("/api/merge", post(routes::merge::merge_files))
+        .route("/api/join", post(routes::join::join_files))
         .route("/api/search", post(routes::search::search_files))
         .route("/api/reply", post(routes::reply::cell_reply_files))
         .route("/api/reply-single", post(routes::reply::cell_reply_file))
@@ -74,7 +79,8 @@ async fn main() -> error::Result<()> {
                 .allow_methods([Method::GET, Method::POST, Method::DELETE])
                 .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, LAST_MODIFIED]),
         )
-        .layer(DefaultBodyLimit::max(800 * 1000 * 1000));
+        .layer(Extension(limits))
+        .layer(DefaultBodyLimit::max(limits.body_limit()));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
 
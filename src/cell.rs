@@ -0,0 +1,374 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use calamine::Data;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rust_xlsxwriter::{Format, Formula, Worksheet, XlsxError};
+
+use crate::error::{Error, Result};
+use crate::formula::rebase;
+
+/// how dates are shown when they're turned into text, matches the excel formats below
+const DATE_TEXT: &str = "%Y/%m/%d";
+const DATE_TIME_TEXT: &str = "%Y/%m/%d %H:%M";
+const MS_PER_DAY: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// A typed cell value, this is what we carry around instead of flattening everything to a string
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Cell {
+    #[default]
+    Empty,
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// a date or date time, serials from either date system and ISO dates all end up here so they
+    /// render the same
+    DateTime(NaiveDateTime),
+    /// an ISO 8601 value that isn't a full date, e.g. a time on its own
+    DateTimeIso(String),
+    /// an excel serial duration, e.g. `[h]:mm:ss`
+    Duration(f64),
+    /// a duration in ISO 8601
+    DurationIso(String),
+    /// an error value such as `#DIV/0!`
+    Error(String),
+    /// a formula (without the `=`) along with its cached value, `origin` is the zero based
+    /// position it had in its sheet so its references can be moved along with it
+    Formula {
+        formula: String,
+        value: Box<Cell>,
+        origin: (u32, u32),
+    },
+}
+
+impl From<&Data> for Cell {
+    fn from(data: &Data) -> Self {
+        match data {
+            Data::String(s) => Cell::String(s.to_owned()),
+            Data::Float(f) => Cell::Float(*f),
+            Data::Int(i) => Cell::Int(*i),
+            Data::Bool(b) => Cell::Bool(*b),
+            Data::DateTime(d) if d.is_duration() => Cell::Duration(d.as_f64()),
+            // calamine takes care of the 1904 date system here
+            Data::DateTime(d) => d
+                .as_datetime()
+                .map_or(Cell::Float(d.as_f64()), Cell::DateTime),
+            Data::DateTimeIso(s) => {
+                parse_iso_date(s).map_or_else(|| Cell::DateTimeIso(s.to_owned()), Cell::DateTime)
+            }
+            Data::DurationIso(s) => Cell::DurationIso(s.to_owned()),
+            Data::Error(e) => Cell::Error(e.to_string()),
+            Data::Empty => Cell::Empty,
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(s: String) -> Self {
+        Cell::String(s)
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(s: &str) -> Self {
+        Cell::String(s.to_string())
+    }
+}
+
+impl From<usize> for Cell {
+    fn from(i: usize) -> Self {
+        Cell::Int(i as i64)
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Empty => Ok(()),
+            Cell::String(s) | Cell::DateTimeIso(s) | Cell::DurationIso(s) | Cell::Error(s) => {
+                write!(f, "{}", s)
+            }
+            Cell::Int(i) => write!(f, "{}", i),
+            Cell::Float(n) | Cell::Duration(n) => write!(f, "{}", n),
+            Cell::DateTime(d) => write!(f, "{}", d.format(default_date_text(d))),
+            Cell::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Cell::Formula { value, .. } => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Cell {
+    /// the text used when searching or comparing cells, borrows when the cell is already text
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            Cell::String(s) | Cell::DateTimeIso(s) | Cell::DurationIso(s) | Cell::Error(s) => {
+                Cow::Borrowed(s)
+            }
+            Cell::Formula { value, .. } => value.as_text(),
+            _ => Cow::Owned(self.to_string()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Cell::Empty => true,
+            Cell::String(s) => s.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// write the cell to a worksheet using the matching excel type
+    pub fn write(
+        &self,
+        worksheet: &mut Worksheet,
+        row: u32,
+        col: u16,
+        formats: &CellFormats,
+    ) -> std::result::Result<(), XlsxError> {
+        match self {
+            // nothing to write, leave it blank
+            Cell::Empty => {}
+            Cell::String(s) | Cell::DateTimeIso(s) | Cell::DurationIso(s) | Cell::Error(s) => {
+                worksheet.write_string(row, col, s)?;
+            }
+            Cell::Int(i) => {
+                worksheet.write_number(row, col, *i as f64)?;
+            }
+            Cell::Float(n) => {
+                worksheet.write_number(row, col, *n)?;
+            }
+            Cell::Bool(b) => {
+                worksheet.write_boolean(row, col, *b)?;
+            }
+            Cell::DateTime(d) => match &formats.dates {
+                DateFormat::Excel => {
+                    let format = if d.time() == NaiveTime::MIN {
+                        &formats.date
+                    } else {
+                        &formats.date_time
+                    };
+                    worksheet.write_number_with_format(row, col, excel_serial(d), format)?;
+                }
+                DateFormat::Text(text) => {
+                    worksheet.write_string(row, col, d.format(text).to_string())?;
+                }
+            },
+            Cell::Duration(n) => {
+                worksheet.write_number_with_format(row, col, *n, &formats.duration)?;
+            }
+            Cell::Formula {
+                formula,
+                value,
+                origin,
+            } => {
+                worksheet.write_formula(
+                    row,
+                    col,
+                    moved_formula(formula, value, *origin, row, col),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// write the cell with a format of its own, e.g. the style of a template's header. Dates and
+    /// durations keep their number formats, empty cells are written blank so fills still show
+    pub fn write_with_format(
+        &self,
+        worksheet: &mut Worksheet,
+        row: u32,
+        col: u16,
+        formats: &CellFormats,
+        format: &Format,
+    ) -> std::result::Result<(), XlsxError> {
+        match self {
+            Cell::Empty => {
+                worksheet.write_blank(row, col, format)?;
+            }
+            Cell::String(s) | Cell::DateTimeIso(s) | Cell::DurationIso(s) | Cell::Error(s) => {
+                worksheet.write_string_with_format(row, col, s, format)?;
+            }
+            Cell::Int(i) => {
+                worksheet.write_number_with_format(row, col, *i as f64, format)?;
+            }
+            Cell::Float(n) => {
+                worksheet.write_number_with_format(row, col, *n, format)?;
+            }
+            Cell::Bool(b) => {
+                worksheet.write_boolean_with_format(row, col, *b, format)?;
+            }
+            Cell::DateTime(_) | Cell::Duration(_) => self.write(worksheet, row, col, formats)?,
+            Cell::Formula {
+                formula,
+                value,
+                origin,
+            } => {
+                let formula = moved_formula(formula, value, *origin, row, col);
+                worksheet.write_formula_with_format(row, col, formula, format)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How date cells are written to the output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DateFormat {
+    /// real excel dates, so they can still be sorted and filtered as dates
+    #[default]
+    Excel,
+    /// text in a strftime style format, e.g. `%d/%m/%Y`
+    Text(String),
+}
+
+impl DateFormat {
+    /// `""` or `"excel"` is a real excel date, anything else is a text format
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+
+        if value.is_empty() || value.eq_ignore_ascii_case("excel") {
+            return Ok(DateFormat::Excel);
+        }
+
+        // chrono only complains about a bad format while writing, catch it early instead
+        if StrftimeItems::new(value).any(|item| matches!(item, Item::Error)) {
+            return Err(Error::InvalidDateFormat {
+                format: value.to_string(),
+            });
+        }
+
+        Ok(DateFormat::Text(value.to_string()))
+    }
+}
+
+/// The number formats needed to show typed cells right in excel
+pub struct CellFormats {
+    pub date: Format,
+    pub date_time: Format,
+    pub duration: Format,
+    pub dates: DateFormat,
+}
+
+impl CellFormats {
+    pub fn new(dates: DateFormat) -> Self {
+        CellFormats {
+            dates,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CellFormats {
+    fn default() -> Self {
+        CellFormats {
+            date: Format::new().set_num_format("yyyy/mm/dd"),
+            date_time: Format::new().set_num_format("yyyy/mm/dd hh:mm"),
+            duration: Format::new().set_num_format("[h]:mm:ss"),
+            dates: DateFormat::Excel,
+        }
+    }
+}
+
+/// the formula of a cell written at `row` and `col`, its references move by as much as the cell
+/// itself moved from `origin`
+fn moved_formula(formula: &str, value: &Cell, origin: (u32, u32), row: u32, col: u16) -> Formula {
+    let formula = rebase(
+        formula,
+        row as i64 - origin.0 as i64,
+        col as i64 - origin.1 as i64,
+    );
+
+    Formula::new(format!("={}", formula)).set_result(value.to_string())
+}
+
+fn default_date_text(date: &NaiveDateTime) -> &'static str {
+    if date.time() == NaiveTime::MIN {
+        DATE_TEXT
+    } else {
+        DATE_TIME_TEXT
+    }
+}
+
+/// ISO dates and date times, ODS files and CSVs have these instead of serials
+pub fn parse_iso_date(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+
+    value
+        .parse::<NaiveDateTime>()
+        .ok()
+        .or_else(|| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok())
+        .or_else(|| {
+            value
+                .parse::<NaiveDate>()
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+/// the serial number of a date in the 1900 date system, which is what we always write
+fn excel_serial(date: &NaiveDateTime) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)
+        .unwrap()
+        .and_time(NaiveTime::MIN);
+    let days = (*date - epoch).num_milliseconds() as f64 / MS_PER_DAY;
+
+    // excel thinks 1900 was a leap year, so serials before march 1900 are a day off
+    if days < 61.0 {
+        days - 1.0
+    } else {
+        days
+    }
+}
+
+/// convert a calamine range row by row
+pub fn data_to_cells<'a>(row: impl IntoIterator<Item = &'a Data>) -> Vec<Cell> {
+    row.into_iter().map(Cell::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cell_text() {
+        assert_eq!(Cell::Int(3).as_text(), "3");
+        assert_eq!(Cell::Float(3.5).as_text(), "3.5");
+        assert_eq!(Cell::Bool(true).as_text(), "TRUE");
+        assert_eq!(Cell::Empty.as_text(), "");
+        assert_eq!(Cell::from("abc").as_text(), "abc");
+    }
+
+    #[test]
+    fn test_date_systems() {
+        use calamine::{ExcelDateTime, ExcelDateTimeType};
+
+        let date_1900 = Data::DateTime(ExcelDateTime::new(
+            45123.5,
+            ExcelDateTimeType::DateTime,
+            false,
+        ));
+        let date_1904 = Data::DateTime(ExcelDateTime::new(
+            43661.5,
+            ExcelDateTimeType::DateTime,
+            true,
+        ));
+        let iso = Data::DateTimeIso("2023-07-16T12:00:00".to_string());
+
+        for data in [&date_1900, &date_1904, &iso] {
+            let cell = Cell::from(data);
+            assert_eq!(cell.as_text(), "2023/07/16 12:00");
+
+            let Cell::DateTime(date) = cell else {
+                panic!("{:?} is not a date", data);
+            };
+            assert_eq!(excel_serial(&date), 45123.5);
+        }
+
+        assert!(DateFormat::parse("%d/%m/%Y").is_ok());
+        assert!(DateFormat::parse("%Q").is_err());
+    }
+}
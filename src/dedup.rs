@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::{Cell, CellFormats};
+use crate::error::{Error, Result};
+use crate::mapping::header_key;
+use crate::File;
+
+/// Which of the rows sharing a key is kept, the others are dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeepPolicy {
+    /// the first one in the merged order
+    #[default]
+    First,
+    /// the last one in the merged order
+    Last,
+    /// the one from the file modified last, the first of them on a tie
+    Newest,
+    /// the one from the main file, the first one if none of them is
+    Main,
+}
+
+impl KeepPolicy {
+    /// `""` keeps the first row, otherwise `"last"`, `"newest"` or `"main"`
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "first" => Ok(KeepPolicy::First),
+            "last" => Ok(KeepPolicy::Last),
+            "newest" => Ok(KeepPolicy::Newest),
+            "main" => Ok(KeepPolicy::Main),
+            _ => Err(Error::InvalidDeduplication {
+                reason: format!(
+                    "{:?} is not a keep policy (first, last, newest or main)",
+                    value.trim()
+                ),
+            }),
+        }
+    }
+}
+
+/// A row that was dropped for having the same key as another one
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateRow {
+    pub file: String,
+    /// the file of the row that was kept instead
+    pub kept_from: String,
+    pub row: Vec<Cell>,
+}
+
+/// Drops the rows that repeat the values of the key columns of another row
+#[derive(Clone, Debug, Default)]
+pub struct Deduplication {
+    /// header names, matched the same way the column mapping matches them
+    pub keys: Vec<String>,
+    pub keep: KeepPolicy,
+}
+
+impl Deduplication {
+    /// drop the duplicate rows of the files, which are looked at in the order they're merged in.
+    /// Rows with all of their keys empty are never duplicates
+    pub fn apply(&self, files: &mut [File]) -> Result<Vec<DuplicateRow>> {
+        let key_columns = files
+            .iter()
+            .map(|file| {
+                let header = file
+                    .header
+                    .iter()
+                    .map(|cell| header_key(&cell.as_text()))
+                    .collect::<Vec<_>>();
+
+                self.keys
+                    .iter()
+                    .map(|name| header.iter().position(|header| *header == header_key(name)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for (k, name) in self.keys.iter().enumerate() {
+            if key_columns.iter().all(|columns| columns[k].is_none()) {
+                return Err(Error::InvalidDeduplication {
+                    reason: format!("none of the files has a {:?} column", name),
+                });
+            }
+        }
+
+        // every row sharing a key, in the order they're merged in
+        let mut groups: HashMap<Vec<String>, Vec<(usize, usize)>> = HashMap::new();
+        for (i, file) in files.iter().enumerate() {
+            for (j, row) in file.rows.iter().enumerate() {
+                let values = key_columns[i]
+                    .iter()
+                    .map(|column| {
+                        column
+                            .and_then(|column| row.get(column))
+                            .map(|cell| cell.as_text().trim().to_string())
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>();
+
+                if values.iter().all(String::is_empty) {
+                    continue;
+                }
+
+                groups.entry(values).or_default().push((i, j));
+            }
+        }
+
+        // the file of the kept row, keyed by the rows that are dropped
+        let mut dropped: HashMap<(usize, usize), usize> = HashMap::new();
+        for rows in groups.values().filter(|rows| rows.len() > 1) {
+            let kept = self.kept(files, rows);
+
+            for row in rows.iter().filter(|row| **row != kept) {
+                dropped.insert(*row, kept.0);
+            }
+        }
+
+        let names = files
+            .iter()
+            .map(|file| file.display_name().replace("-MAIN", ""))
+            .collect::<Vec<_>>();
+        let mut duplicates = vec![];
+
+        for (i, file) in files.iter_mut().enumerate() {
+            let rows = std::mem::take(&mut file.rows);
+            let origins = std::mem::take(&mut file.origins);
+
+            for (j, row) in rows.into_iter().enumerate() {
+                match dropped.get(&(i, j)) {
+                    Some(kept) => duplicates.push(DuplicateRow {
+                        file: names[i].clone(),
+                        kept_from: names[*kept].clone(),
+                        row,
+                    }),
+                    None => {
+                        file.rows.push(row);
+                        file.origins.extend(origins.get(j));
+                    }
+                }
+            }
+        }
+
+        Ok(duplicates)
+    }
+
+    /// the row to keep out of rows sharing a key
+    fn kept(&self, files: &[File], rows: &[(usize, usize)]) -> (usize, usize) {
+        match self.keep {
+            KeepPolicy::First => rows[0],
+            KeepPolicy::Last => rows[rows.len() - 1],
+            KeepPolicy::Newest => {
+                let modified = |row: &(usize, usize)| {
+                    NaiveDateTime::parse_from_str(&files[row.0].last_modified, "%Y/%m/%d %H:%M")
+                        .ok()
+                };
+
+                // a file without a date is older than any that has one
+                rows.iter().fold(rows[0], |newest, row| {
+                    if modified(row) > modified(&newest) {
+                        *row
+                    } else {
+                        newest
+                    }
+                })
+            }
+            KeepPolicy::Main => rows
+                .iter()
+                .find(|row| files[row.0].is_main)
+                .copied()
+                .unwrap_or(rows[0]),
+        }
+    }
+}
+
+/// list the dropped rows and where they came from on a sheet of their own
+pub fn write_duplicates(
+    workbook: &mut Workbook,
+    header: &[Cell],
+    duplicates: &[DuplicateRow],
+    formats: &CellFormats,
+) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Duplicates")
+        .context("error setting name of duplicates sheet")?;
+
+    let intro_headers = [Cell::from("File Name"), Cell::from("Kept From")];
+    for (j, cell) in intro_headers.iter().chain(header).enumerate() {
+        cell.write(worksheet, 0, j as u16, formats)
+            .context("error writing header")?;
+    }
+
+    for (i, duplicate) in duplicates.iter().enumerate() {
+        let row = (i + 1) as u32;
+        let intro = [
+            Cell::from(duplicate.file.as_str()),
+            Cell::from(duplicate.kept_from.as_str()),
+        ];
+
+        for (j, cell) in intro.iter().chain(&duplicate.row).enumerate() {
+            cell.write(worksheet, row, j as u16, formats)
+                .context("error writing duplicate row")?;
+        }
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file;
+
+    /// the files with the `ID` 1 in all of them, the main one is the first
+    fn files() -> Vec<File> {
+        let mut a = test_file("a.xlsx", &["ID", "Value"], &[&["1", "a"], &["", "a"]]);
+        a.last_modified = "2023/01/01 00:00".to_string();
+        a.is_main = true;
+        let mut b = test_file("b.xlsx", &["ID", "Value"], &[&["1", "b"], &["", "b"]]);
+        b.last_modified = "2023/03/01 00:00".to_string();
+        let mut c = test_file("c.xlsx", &["ID", "Value"], &[&["1", "c"], &["2", "c"]]);
+        c.last_modified = "2023/02/01 00:00".to_string();
+
+        vec![a, b, c]
+    }
+
+    /// the values of the rows with the `ID` 1 that are kept
+    fn kept(keep: KeepPolicy) -> Vec<String> {
+        let dedup = Deduplication {
+            keys: vec!["id".to_string()],
+            keep,
+        };
+        let mut files = files();
+        let duplicates = dedup.apply(&mut files).unwrap();
+        assert_eq!(duplicates.len(), 2);
+
+        files
+            .iter()
+            .flat_map(|file| &file.rows)
+            .filter(|row| row[0].as_text() == "1")
+            .map(|row| row[1].as_text().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_keep_first() {
+        assert_eq!(kept(KeepPolicy::First), ["a"]);
+    }
+
+    #[test]
+    fn test_keep_last() {
+        assert_eq!(kept(KeepPolicy::Last), ["c"]);
+    }
+
+    #[test]
+    fn test_keep_newest() {
+        assert_eq!(kept(KeepPolicy::Newest), ["b"]);
+    }
+
+    #[test]
+    fn test_keep_main() {
+        assert_eq!(kept(KeepPolicy::Main), ["a"]);
+    }
+
+    #[test]
+    fn test_missing_key_column() {
+        let dedup = Deduplication {
+            keys: vec!["Missing".to_string()],
+            keep: KeepPolicy::First,
+        };
+        assert!(dedup.apply(&mut files()).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::borrow::Cow;
+
+use anyhow::Context;
+use calamine::{Data, Range};
+use chrono::{NaiveDate, NaiveDateTime};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1256};
+use tracing::debug;
+
+use crate::error::Result;
+
+/// the delimiters we try when sniffing, in order of preference
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// how many lines are looked at when sniffing the delimiter
+const SNIFF_LINES: usize = 20;
+
+/// read a CSV/TSV file into a range, so it can go through the same path as a workbook sheet
+pub fn read_delimited(bytes: &[u8], file_name: &str) -> Result<Range<Data>> {
+    let text = decode(bytes);
+    let delimiter = sniff_delimiter(&text, file_name);
+    debug!("Delimiter of {:?}: {:?}", file_name, delimiter as char);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes());
+
+    let mut rows: Vec<Vec<Data>> = vec![];
+    for record in reader.records() {
+        let record = record.with_context(|| format!("error reading {:?}", file_name))?;
+        rows.push(record.iter().map(parse_value).collect());
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if rows.is_empty() || width == 0 {
+        return Ok(Range::empty());
+    }
+
+    let mut range = Range::new((0, 0), (rows.len() as u32 - 1, width as u32 - 1));
+    for (i, row) in rows.into_iter().enumerate() {
+        for (j, value) in row.into_iter().enumerate() {
+            range.set_value((i as u32, j as u32), value);
+        }
+    }
+
+    Ok(range)
+}
+
+/// decode the raw bytes to text, a BOM always wins, then UTF-16 without a BOM, then UTF-8.
+/// Anything else is most likely an old export from an Arabic Windows machine
+fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        debug!("Found a {} BOM", encoding.name());
+        return encoding.decode_without_bom_handling(&bytes[bom_length..]).0;
+    }
+
+    if let Some(encoding) = sniff_utf16(bytes) {
+        debug!("Looks like {} without a BOM", encoding.name());
+        return encoding.decode_without_bom_handling(bytes).0;
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => WINDOWS_1256.decode_without_bom_handling(bytes).0,
+    }
+}
+
+/// mostly-ASCII text in UTF-16 has a zero byte in every other position
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(1024)];
+    if sample.len() < 4 {
+        return None;
+    }
+
+    let pairs = sample.len() / 2;
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|b| **b == 0)
+        .count();
+
+    if odd_zeros * 10 > pairs * 4 && even_zeros * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 > pairs * 4 && odd_zeros * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// pick the delimiter that splits the first lines into the same number of fields. If none is
+/// consistent, the one that shows up the most wins
+fn sniff_delimiter(text: &str, file_name: &str) -> u8 {
+    let is_tsv = file_name.to_ascii_lowercase().ends_with(".tsv");
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(SNIFF_LINES)
+        .collect::<Vec<_>>();
+
+    let mut best: Option<(bool, usize, u8)> = None;
+
+    for delimiter in DELIMITERS {
+        let counts = lines
+            .iter()
+            .map(|line| count_unquoted(line, delimiter))
+            .collect::<Vec<_>>();
+
+        let total: usize = counts.iter().sum();
+        if total == 0 {
+            continue;
+        }
+
+        let consistent = counts.iter().all(|count| *count == counts[0]);
+
+        // strictly better only, so earlier delimiters win ties
+        if !matches!(best, Some((c, t, _)) if (c, t) >= (consistent, total)) {
+            best = Some((consistent, total, delimiter));
+        }
+    }
+
+    match best {
+        Some((_, _, delimiter)) => delimiter,
+        None if is_tsv => b'\t',
+        None => b',',
+    }
+}
+
+/// count a delimiter in a line, ignoring the ones in quoted fields
+fn count_unquoted(line: &str, delimiter: u8) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+
+    for byte in line.bytes() {
+        if byte == b'"' {
+            quoted = !quoted;
+        } else if byte == delimiter && !quoted {
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// plain numbers become numbers, anything that could be an identifier (leading zeros, plus
+/// signs, ...) stays text
+fn parse_value(value: &str) -> Data {
+    if value.is_empty() {
+        return Data::Empty;
+    }
+
+    let trimmed = value.trim();
+    let digits = trimmed.strip_prefix('-').unwrap_or(trimmed);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+
+    if leading_zero || digits.starts_with('+') || digits.is_empty() {
+        return Data::String(value.to_string());
+    }
+
+    // keep ISO dates as dates so they render like the ones from workbooks
+    if trimmed.parse::<NaiveDate>().is_ok() || trimmed.parse::<NaiveDateTime>().is_ok() {
+        return Data::DateTimeIso(trimmed.to_string());
+    }
+
+    if let Ok(i) = trimmed.parse::<i64>() {
+        return Data::Int(i);
+    }
+
+    match trimmed.parse::<f64>() {
+        Ok(f) if f.is_finite() && digits.starts_with(|c: char| c.is_ascii_digit()) => {
+            Data::Float(f)
+        }
+        _ => Data::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_delimiter() {
+        assert_eq!(sniff_delimiter("a;b;c\n1;2;3", "x.csv"), b';');
+        assert_eq!(sniff_delimiter("a\tb\n\"1,5\"\t2", "x.csv"), b'\t');
+        assert_eq!(sniff_delimiter("a,b\n1,2", "x.csv"), b',');
+        assert_eq!(sniff_delimiter("a", "x.tsv"), b'\t');
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"\xEF\xBB\xBFabc"), "abc");
+        assert_eq!(decode(b"\xFF\xFEa\0b\0"), "ab");
+        assert_eq!(decode(b"a\0,\0b\0"), "a,b");
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("12"), Data::Int(12));
+        assert_eq!(parse_value("-1.5"), Data::Float(-1.5));
+        assert_eq!(parse_value("007"), Data::String("007".to_string()));
+        assert_eq!(parse_value("inf"), Data::String("inf".to_string()));
+        assert_eq!(parse_value(""), Data::Empty);
+    }
+}
@@ -4,22 +4,83 @@ use axum::{
     Json,
 };
 use serde_json::json;
+use size::Size;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 // TODO: Use the right status codes for the right errors
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Only one sheet per file is supported")]
-    SheetLimitExceeded,
+    #[error("Sheet {sheet:?} was not found in {file:?}")]
+    SheetNotFound { file: String, sheet: String },
+    #[error("{file:?} is not a format we can read (xlsx, xls, xlsb, ods, csv or tsv)")]
+    UnsupportedFormat { file: String },
+    #[error("Invalid options for {file:?}: {reason}")]
+    InvalidOptions { file: String, reason: String },
+    #[error("Got {values} {field} values for {files} files")]
+    OptionCountMismatch {
+        field: String,
+        values: usize,
+        files: usize,
+    },
+    #[error("Invalid column mapping: {reason}")]
+    InvalidMapping { reason: String },
+    #[error("Invalid deduplication: {reason}")]
+    InvalidDeduplication { reason: String },
+    #[error("Invalid sort: {reason}")]
+    InvalidSort { reason: String },
+    #[error("Invalid join: {reason}")]
+    InvalidJoin { reason: String },
+    #[error("Invalid provenance columns: {reason}")]
+    InvalidProvenance { reason: String },
+    #[error("Invalid query: {reason}")]
+    InvalidQuery { reason: String },
+    #[error("Invalid normalization: {reason}")]
+    InvalidNormalization { reason: String },
+    #[error("{file:?} doesn't have any workbooks in it")]
+    EmptyArchive { file: String },
+    #[error("{value:?} is not a valid {field} value, it has to be true or false")]
+    InvalidFlag { field: String, value: String },
+    #[error("{format:?} is not a valid date format")]
+    InvalidDateFormat { format: String },
+    #[error("{mode:?} is not a formula mode (keep or values)")]
+    InvalidFormulaMode { mode: String },
+    #[error("{file:?} is larger than the limit of {limit} per file")]
+    FileTooLarge { file: String, limit: Size },
+    #[error(
+        "The upload is larger than the limit of {limit} per request, it went over at {file:?}"
+    )]
+    RequestTooLarge { file: String, limit: Size },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
+        let status_code = match self {
+            Error::SheetNotFound { .. }
+            | Error::UnsupportedFormat { .. }
+            | Error::EmptyArchive { .. }
+            | Error::InvalidOptions { .. }
+            | Error::OptionCountMismatch { .. }
+            | Error::InvalidMapping { .. }
+            | Error::InvalidDeduplication { .. }
+            | Error::InvalidSort { .. }
+            | Error::InvalidJoin { .. }
+            | Error::InvalidProvenance { .. }
+            | Error::InvalidQuery { .. }
+            | Error::InvalidNormalization { .. }
+            | Error::InvalidFlag { .. }
+            | Error::InvalidDateFormat { .. }
+            | Error::InvalidFormulaMode { .. } => StatusCode::BAD_REQUEST,
+            Error::FileTooLarge { .. } | Error::RequestTooLarge { .. } => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            Error::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
         let (status_code, body) = (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            status_code,
             Json(json!(
                 {
                     "error": self.to_string(),
@@ -0,0 +1,93 @@
+use crate::cell::Cell;
+
+/// what the total and signature rows at the bottom of a report usually start with
+const DEFAULT_PATTERNS: [&str; 12] = [
+    "total",
+    "grand total",
+    "subtotal",
+    "sum",
+    "signature",
+    "signed",
+    "prepared by",
+    "approved by",
+    "الإجمالي",
+    "الاجمالي",
+    "المجموع",
+    "التوقيع",
+];
+
+/// Finds the rows trailing the data of a sheet, totals, signatures and mostly empty rows
+#[derive(Clone, Debug)]
+pub struct FooterDetector {
+    /// matched against the start of a cell's text, case insensitive
+    pub patterns: Vec<String>,
+}
+
+impl Default for FooterDetector {
+    fn default() -> Self {
+        FooterDetector {
+            patterns: DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+impl FooterDetector {
+    /// how many of the last rows are footer rows, `width` is how many columns the sheet has
+    pub fn footer_len(&self, rows: &[Vec<Cell>], width: usize) -> usize {
+        rows.iter()
+            .rev()
+            .take_while(|row| self.is_footer(row, width))
+            .count()
+    }
+
+    /// a row is a footer if less than a third of it is filled or one of its cells starts with
+    /// one of the patterns, "Total:" and "Total Sales" both start with "total"
+    fn is_footer(&self, row: &[Cell], width: usize) -> bool {
+        let filled = row.iter().filter(|cell| !cell.is_empty()).count();
+        if filled * 3 < width.max(row.len()).max(1) {
+            return true;
+        }
+
+        row.iter().any(|cell| {
+            let Cell::String(text) = cell else {
+                return false;
+            };
+            let text = text.trim().to_lowercase();
+
+            self.patterns.iter().any(|pattern| {
+                let pattern = pattern.trim().to_lowercase();
+
+                !pattern.is_empty()
+                    && text.starts_with(&pattern)
+                    && !text[pattern.len()..]
+                        .chars()
+                        .next()
+                        .is_some_and(char::is_alphanumeric)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_footer_len() {
+        let row = |cells: &[&str]| cells.iter().map(|c| Cell::from(*c)).collect::<Vec<_>>();
+        let rows = vec![
+            row(&["Ali", "Cairo", "3", "Totally fine"]),
+            row(&["Sum of all", "", "3", ""]),
+            row(&["الإجمالي", "", "3", ""]),
+            row(&["", "Signed:", "", ""]),
+            row(&["", "", "", ""]),
+        ];
+
+        assert_eq!(FooterDetector::default().footer_len(&rows, 4), 4);
+
+        let detector = FooterDetector {
+            patterns: vec!["Signed".to_string()],
+        };
+        assert_eq!(detector.footer_len(&rows, 4), 2);
+    }
+}
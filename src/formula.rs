@@ -0,0 +1,411 @@
+use anyhow::Context;
+use calamine::{Data, Range};
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::Cell;
+use crate::error::{Error, Result};
+
+/// the last column and row of a sheet (XFD1048576)
+const MAX_COL: i64 = 16_384;
+const MAX_ROW: i64 = 1_048_576;
+
+/// What happens to the formulas of the uploaded workbooks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FormulaMode {
+    /// only the cached values are read, formulas aren't looked at
+    #[default]
+    Cached,
+    /// the formulas are written back out, their references follow the cell wherever it ends up
+    Keep,
+    /// only the cached values, but formulas that don't have one are reported
+    Values,
+}
+
+impl FormulaMode {
+    /// `""` keeps the cached values, otherwise `"keep"` or `"values"`
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "cached" => Ok(FormulaMode::Cached),
+            "keep" => Ok(FormulaMode::Keep),
+            "values" => Ok(FormulaMode::Values),
+            _ => Err(Error::InvalidFormulaMode {
+                mode: value.trim().to_string(),
+            }),
+        }
+    }
+
+    /// whether the formulas of a sheet have to be read at all
+    pub fn reads_formulas(&self) -> bool {
+        *self != FormulaMode::Cached
+    }
+}
+
+/// A formula cell that had no cached value, so all we could read was an empty cell
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingValue {
+    pub file: String,
+    pub sheet: String,
+    /// the address in the original sheet, e.g. `B7`
+    pub cell: String,
+    pub formula: String,
+}
+
+/// grow the values of a sheet so they cover its formulas too, a formula without a cached value
+/// can be outside of the used range
+pub fn cover(values: Range<Data>, formulas: &Range<String>) -> Range<Data> {
+    let (Some(formulas_start), Some(formulas_end)) = (formulas.start(), formulas.end()) else {
+        return values;
+    };
+
+    let (start, end) = match (values.start(), values.end()) {
+        (Some(start), Some(end)) => (
+            (start.0.min(formulas_start.0), start.1.min(formulas_start.1)),
+            (end.0.max(formulas_end.0), end.1.max(formulas_end.1)),
+        ),
+        _ => (formulas_start, formulas_end),
+    };
+
+    if values.start() == Some(start) && values.end() == Some(end) {
+        return values;
+    }
+
+    let values_start = values.start().unwrap_or_default();
+    let mut covered = Range::new(start, end);
+    for (row, col, value) in values.used_cells() {
+        covered.set_value(
+            (values_start.0 + row as u32, values_start.1 + col as u32),
+            value.clone(),
+        );
+    }
+
+    covered
+}
+
+/// put the formulas of a sheet in its rows, `start` is where the rows start in the sheet. Returns
+/// the formulas that had no cached value when they're asked for
+pub fn apply_formulas(
+    rows: &mut [Vec<Cell>],
+    start: (u32, u32),
+    formulas: &Range<String>,
+    mode: FormulaMode,
+    file_name: &str,
+    sheet_name: &str,
+) -> Vec<MissingValue> {
+    let mut missing = vec![];
+    let formulas_start = formulas.start().unwrap_or_default();
+
+    for (row, col, formula) in formulas.used_cells() {
+        let origin = (formulas_start.0 + row as u32, formulas_start.1 + col as u32);
+        let cell = origin
+            .0
+            .checked_sub(start.0)
+            .zip(origin.1.checked_sub(start.1))
+            .and_then(|(row, col)| rows.get_mut(row as usize)?.get_mut(col as usize));
+
+        let Some(cell) = cell else {
+            continue;
+        };
+
+        match mode {
+            FormulaMode::Keep => {
+                let value = std::mem::take(cell);
+                *cell = Cell::Formula {
+                    formula: formula.clone(),
+                    value: Box::new(value),
+                    origin,
+                };
+            }
+            FormulaMode::Values if cell.is_empty() => missing.push(MissingValue {
+                file: file_name.to_string(),
+                sheet: sheet_name.to_string(),
+                cell: cell_name(origin.0 as i64 + 1, origin.1 as i64 + 1),
+                formula: formula.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    missing
+}
+
+/// list the formulas that had no cached value on a sheet of their own
+pub fn write_missing_values(workbook: &mut Workbook, missing: &[MissingValue]) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Missing Values")
+        .context("error setting name of missing values sheet")?;
+
+    for (j, header) in ["File Name", "Sheet", "Cell", "Formula"].iter().enumerate() {
+        worksheet
+            .write_string(0, j as u16, *header)
+            .context("error writing header")?;
+    }
+
+    for (i, value) in missing.iter().enumerate() {
+        let row = (i + 1) as u32;
+        let formula = format!("={}", value.formula);
+
+        for (j, text) in [&value.file, &value.sheet, &value.cell, &formula]
+            .iter()
+            .enumerate()
+        {
+            worksheet
+                .write_string(row, j as u16, text.as_str())
+                .context("error writing missing value")?;
+        }
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+/// shift the relative references of a formula by `rows` and `cols`, the way excel does when a
+/// cell is copied. `$` anchored parts stay put and references pushed off the sheet become `#REF!`.
+/// References to another sheet (`Data!A1`) are left alone, only this sheet's rows are moved
+pub fn rebase(formula: &str, rows: i64, cols: i64) -> String {
+    if rows == 0 && cols == 0 {
+        return formula.to_string();
+    }
+
+    let chars: Vec<char> = formula.chars().collect();
+    let mut rebased = String::with_capacity(formula.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        // strings and quoted sheet names are copied as they are, with the reference after a sheet
+        if c == '"' || c == '\'' {
+            let mut end = closing_quote(&chars, i);
+            if c == '\'' {
+                end = sheet_reference_end(&chars, end);
+            }
+            rebased.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
+        if !is_token_char(c) {
+            rebased.push(c);
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < chars.len() && is_token_char(chars[i]) {
+            i += 1;
+        }
+        let token: String = chars[start..i].iter().collect();
+
+        // function names aren't references
+        let next = chars.get(i).copied();
+        if next == Some('(') {
+            rebased.push_str(&token);
+            continue;
+        }
+
+        if next == Some('!') {
+            let end = sheet_reference_end(&chars, i);
+            rebased.extend(&chars[start..end]);
+            i = end;
+            continue;
+        }
+
+        // a whole column (`A:C`) or row (`1:3`) only makes sense next to a colon
+        let in_range = next == Some(':') || (start > 0 && chars[start - 1] == ':');
+        match shift_reference(&token, rows, cols, in_range) {
+            Some(shifted) => rebased.push_str(&shifted),
+            None => rebased.push_str(&token),
+        }
+    }
+
+    rebased
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '$' | '_' | '.')
+}
+
+/// the index right after the reference following a sheet name, `i` is right after the name. A
+/// range (`Data!A1:B2`) is on that sheet as a whole
+fn sheet_reference_end(chars: &[char], mut i: usize) -> usize {
+    if chars.get(i) != Some(&'!') {
+        return i;
+    }
+    i += 1;
+
+    let token_end = |mut i: usize| {
+        while i < chars.len() && is_token_char(chars[i]) {
+            i += 1;
+        }
+        i
+    };
+    i = token_end(i);
+    if chars.get(i) == Some(&':') {
+        i = token_end(i + 1);
+    }
+
+    i
+}
+
+/// the index right after the quote closing the one at `start`, a doubled quote is an escape
+fn closing_quote(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+
+            return i + 1;
+        }
+
+        i += 1;
+    }
+
+    chars.len()
+}
+
+/// shift a token if it's a reference, `None` if it's anything else (a name, a number, ...)
+fn shift_reference(token: &str, rows: i64, cols: i64, in_range: bool) -> Option<String> {
+    let (col_anchor, rest) = match token.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let letters_end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let (letters, rest) = rest.split_at(letters_end);
+    let (row_anchor, digits) = match rest.strip_prefix('$') {
+        Some(digits) => (true, digits),
+        None => (false, rest),
+    };
+
+    if letters.len() > 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // without a column the first `$` anchors the row, as in `$1:$3`
+    let (col_anchor, row_anchor) = match letters {
+        "" => (false, col_anchor || row_anchor),
+        _ => (col_anchor, row_anchor),
+    };
+
+    let col = match letters {
+        "" => None,
+        letters => Some(column_index(letters)?),
+    };
+    let row = match digits {
+        "" => None,
+        digits => Some(digits.parse::<i64>().ok().filter(|row| *row >= 1)?),
+    };
+
+    // `A$` isn't anything
+    if row.is_none() && row_anchor {
+        return None;
+    }
+
+    let shift = |index: i64, anchored: bool, by: i64, max: i64| {
+        let index = if anchored { index } else { index + by };
+        (1..=max).contains(&index).then_some(index)
+    };
+
+    let (col, row) = match (col, row) {
+        (Some(col), Some(row)) => (
+            Some(shift(col, col_anchor, cols, MAX_COL)),
+            Some(shift(row, row_anchor, rows, MAX_ROW)),
+        ),
+        (Some(col), None) if in_range => (Some(shift(col, col_anchor, cols, MAX_COL)), None),
+        (None, Some(row)) if in_range => (None, Some(shift(row, row_anchor, rows, MAX_ROW))),
+        _ => return None,
+    };
+
+    if col == Some(None) || row == Some(None) {
+        return Some("#REF!".to_string());
+    }
+
+    let mut shifted = String::new();
+    if let Some(Some(col)) = col {
+        if col_anchor {
+            shifted.push('$');
+        }
+        shifted.push_str(&column_name(col));
+    }
+    if let Some(Some(row)) = row {
+        if row_anchor {
+            shifted.push('$');
+        }
+        shifted.push_str(&row.to_string());
+    }
+
+    Some(shifted)
+}
+
+/// `A` is 1, `AA` is 27, anything past the last column isn't a column
+fn column_index(letters: &str) -> Option<i64> {
+    let index = letters.chars().fold(0, |index, c| {
+        index * 26 + (c.to_ascii_uppercase() as i64 - 'A' as i64 + 1)
+    });
+
+    (index <= MAX_COL).then_some(index)
+}
+
+fn column_name(mut index: i64) -> String {
+    let mut name = vec![];
+
+    while index > 0 {
+        let rem = (index - 1) % 26;
+        name.push((b'A' + rem as u8) as char);
+        index = (index - 1) / 26;
+    }
+
+    name.iter().rev().collect()
+}
+
+/// the zero based row and column of an A1 cell name, `$` anchors are allowed
+pub fn parse_cell_name(name: &str) -> Option<(u32, u32)> {
+    let name = name.trim().replace('$', "");
+    let letters_end = name.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (letters, digits) = name.split_at(letters_end);
+
+    if letters.is_empty() || letters.len() > 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let col = column_index(letters)?;
+    let row = digits
+        .parse::<i64>()
+        .ok()
+        .filter(|row| (1..=MAX_ROW).contains(row))?;
+
+    Some(((row - 1) as u32, (col - 1) as u32))
+}
+
+/// the A1 name of a cell, both one based
+pub fn cell_name(row: i64, col: i64) -> String {
+    format!("{}{}", column_name(col), row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_formula() {
+        assert_eq!(rebase("SUM(A1:B2)*$C$1", 3, 5), "SUM(F4:G5)*$C$1");
+        assert_eq!(rebase("A$1+$A1", 2, 2), "C$1+$A3");
+        assert_eq!(rebase("Data!A1&\"A1\"", 1, 0), "Data!A1&\"A1\"");
+        assert_eq!(rebase("SUM(Data!A1:B2)+A1", 1, 0), "SUM(Data!A1:B2)+A2");
+        assert_eq!(rebase("SUM(A:A)+LOG10(B2)", 0, 1), "SUM(B:B)+LOG10(C2)");
+        assert_eq!(rebase("A2-1.5", -1, 0), "A1-1.5");
+        assert_eq!(rebase("A1", -1, 0), "#REF!");
+        assert_eq!(rebase("'My Sheet'!B3", 1, 1), "'My Sheet'!B3");
+        assert_eq!(rebase("SUM($1:$2)+SUM(3:4)", 1, 0), "SUM($1:$2)+SUM(4:5)");
+        assert_eq!(parse_cell_name("$B$7"), Some((6, 1)));
+        assert_eq!(parse_cell_name("B"), None);
+    }
+}
@@ -0,0 +1,167 @@
+use calamine::Dimensions;
+
+use crate::cell::Cell;
+use crate::error::{Error, Result};
+
+/// what goes between the parts of a flattened header, e.g. "Sales / Q1"
+const SEPARATOR: &str = " / ";
+
+/// Where the header of a sheet is and how many rows it spans
+#[derive(Clone, Copy, Debug)]
+pub struct HeaderSpec {
+    /// zero based, anything above it (report titles and such) is dropped
+    pub row: usize,
+    /// a header spanning more than one row is flattened into a single row
+    pub rows: usize,
+}
+
+impl Default for HeaderSpec {
+    fn default() -> Self {
+        HeaderSpec { row: 0, rows: 1 }
+    }
+}
+
+impl HeaderSpec {
+    /// the zero based row the data starts at, right under the header
+    pub fn data_start(&self) -> usize {
+        self.row + self.rows.max(1)
+    }
+
+    /// split the rows of a sheet into its header and the data below it, `merged_regions` have to
+    /// be relative to `rows` (see [`relative_regions`])
+    pub fn split(
+        &self,
+        file_name: &str,
+        mut rows: Vec<Vec<Cell>>,
+        merged_regions: &[Dimensions],
+    ) -> Result<(Vec<Cell>, Vec<Vec<Cell>>)> {
+        if rows.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+
+        let end = self.data_start();
+        if end > rows.len() {
+            return Err(Error::InvalidOptions {
+                file: file_name.to_string(),
+                reason: format!(
+                    "the header ends at row {} but the sheet only has {} rows",
+                    end,
+                    rows.len()
+                ),
+            });
+        }
+
+        let data = rows.split_off(end);
+        let header_rows = &rows[self.row..];
+        let width = header_rows
+            .iter()
+            .chain(&data)
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0);
+
+        // a single row is kept as it is, typed cells and all
+        let mut header = match header_rows {
+            [header] => header.clone(),
+            _ => flatten(header_rows, self.row, merged_regions, width),
+        };
+        header.resize(width, Cell::Empty);
+
+        Ok((header, data))
+    }
+}
+
+/// flatten several header rows into one. A merged group cell counts for every column under it,
+/// so a "Sales" group over "Q1" and "Q2" gives "Sales / Q1" and "Sales / Q2"
+fn flatten(
+    header_rows: &[Vec<Cell>],
+    first_row: usize,
+    merged_regions: &[Dimensions],
+    width: usize,
+) -> Vec<Cell> {
+    (0..width)
+        .map(|col| {
+            let mut parts: Vec<String> = vec![];
+
+            for (i, row) in header_rows.iter().enumerate() {
+                let region = merged_regions
+                    .iter()
+                    .find(|region| region.contains((first_row + i) as u32, col as u32));
+
+                // the value of a merged region is in its top left cell
+                let cell = match region {
+                    Some(region) => (region.start.0 as usize)
+                        .checked_sub(first_row)
+                        .and_then(|row| header_rows.get(row))
+                        .and_then(|row| row.get(region.start.1 as usize)),
+                    None => row.get(col),
+                };
+
+                let Some(text) = cell.map(|cell| cell.as_text().trim().to_string()) else {
+                    continue;
+                };
+
+                // a cell merged down over several header rows only counts once
+                if !text.is_empty() && parts.last() != Some(&text) {
+                    parts.push(text);
+                }
+            }
+
+            if parts.is_empty() {
+                Cell::Empty
+            } else {
+                Cell::String(parts.join(SEPARATOR))
+            }
+        })
+        .collect()
+}
+
+/// move merged regions from sheet coordinates to the coordinates of the rows of a range that
+/// starts at `start`, calamine ranges start at the first used cell rather than at A1
+pub fn relative_regions(merged_regions: &[Dimensions], start: (u32, u32)) -> Vec<Dimensions> {
+    merged_regions
+        .iter()
+        .filter(|region| region.end.0 >= start.0 && region.end.1 >= start.1)
+        .map(|region| Dimensions {
+            start: (
+                region.start.0.saturating_sub(start.0),
+                region.start.1.saturating_sub(start.1),
+            ),
+            end: (region.end.0 - start.0, region.end.1 - start.1),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten_header() {
+        let rows = vec![
+            vec!["Report".into(), Cell::Empty, Cell::Empty],
+            vec!["Name".into(), "Sales".into(), Cell::Empty],
+            vec![Cell::Empty, "Q1".into(), "Q2".into()],
+            vec!["Ali".into(), Cell::Int(1), Cell::Int(2)],
+        ];
+        let merged_regions = [
+            Dimensions {
+                start: (1, 0),
+                end: (2, 0),
+            },
+            Dimensions {
+                start: (1, 1),
+                end: (1, 2),
+            },
+        ];
+
+        let spec = HeaderSpec { row: 1, rows: 2 };
+        let (header, data) = spec.split("a.xlsx", rows, &merged_regions).unwrap();
+
+        assert_eq!(
+            header,
+            vec!["Name".into(), "Sales / Q1".into(), "Sales / Q2".into()]
+        );
+        assert_eq!(data, vec![vec!["Ali".into(), Cell::Int(1), Cell::Int(2)]]);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::{Cell, CellFormats, DateFormat};
+use crate::error::{Error, Result};
+use crate::mapping::header_key;
+use crate::File;
+
+/// Which rows a join keeps besides the ones whose key is in both files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JoinType {
+    /// only the rows whose key is in both files
+    Inner,
+    /// every row of the main file, with empty cells where the other file has no such key
+    #[default]
+    Left,
+    /// every row of both files
+    Full,
+}
+
+impl JoinType {
+    /// `""` is a left join, like a VLOOKUP
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "left" => Ok(JoinType::Left),
+            "inner" => Ok(JoinType::Inner),
+            "full" => Ok(JoinType::Full),
+            _ => Err(invalid(format!(
+                "{:?} is not a join type (inner, left or full)",
+                value.trim()
+            ))),
+        }
+    }
+}
+
+/// A key that's in one of the files but not in the other
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnmatchedKey {
+    pub file: String,
+    /// the values of the key columns, separated by ` | `
+    pub key: String,
+    /// the file the key wasn't found in
+    pub missing_from: String,
+}
+
+pub struct JoinFiles {
+    /// the header comes first
+    pub rows: Vec<Vec<Cell>>,
+    pub date_format: DateFormat,
+    pub unmatched: Vec<UnmatchedKey>,
+}
+
+impl JoinFiles {
+    /// save the joined file to a buffer
+    pub fn write_to_buffer(&mut self) -> Result<Vec<u8>> {
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet();
+        let formats = CellFormats::new(self.date_format.clone());
+
+        for (i, row) in self.rows.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                cell.write(worksheet, i as u32, j as u16, &formats)
+                    .context("error writing to the new worksheet")?;
+            }
+        }
+
+        if !self.unmatched.is_empty() {
+            write_unmatched_keys(&mut workbook, &self.unmatched)?;
+        }
+
+        let buf = workbook
+            .save_to_buffer()
+            .context("Failed to save workbook to buffer")?
+            .to_vec();
+
+        Ok(buf)
+    }
+}
+
+/// join the other files to the main one, one after the other, on the key columns. A key that's
+/// in a file more than once is looked up the way VLOOKUP does, the first row with it wins
+pub fn join(
+    main: File,
+    others: &[File],
+    keys: &[String],
+    join_type: JoinType,
+) -> Result<(Vec<Vec<Cell>>, Vec<UnmatchedKey>)> {
+    let main_name = main.display_name().replace("-MAIN", "");
+    let main_keys = key_columns(&main, keys)?;
+    let mut header = main.header;
+    let mut rows = main.rows;
+    let mut unmatched = vec![];
+
+    for other in others {
+        let other_name = other.display_name();
+        let other_keys = key_columns(other, keys)?;
+        let extra_columns = (0..other.header.len())
+            .filter(|column| !other_keys.contains(column))
+            .collect::<Vec<_>>();
+
+        let mut lookup: HashMap<Vec<String>, usize> = HashMap::new();
+        for (i, row) in other.rows.iter().enumerate() {
+            if let Some(key) = key_of(row, &other_keys) {
+                lookup.entry(key).or_insert(i);
+            }
+        }
+
+        let width = header.len();
+        let extras = |row: Option<&Vec<Cell>>| {
+            extra_columns
+                .iter()
+                .map(|column| {
+                    row.and_then(|row| row.get(*column))
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut matched = HashSet::new();
+        let mut joined = vec![];
+        for mut row in rows {
+            let key = key_of(&row, &main_keys);
+            let found = key.as_ref().and_then(|key| lookup.get(key));
+
+            match (found, key) {
+                (Some(i), _) => {
+                    matched.insert(*i);
+                }
+                (None, Some(key)) => unmatched.push(UnmatchedKey {
+                    file: main_name.clone(),
+                    key: key.join(" | "),
+                    missing_from: other_name.clone(),
+                }),
+                (None, None) => {}
+            }
+
+            if found.is_none() && join_type == JoinType::Inner {
+                continue;
+            }
+
+            row.resize(width, Cell::Empty);
+            row.extend(extras(found.map(|i| &other.rows[*i])));
+            joined.push(row);
+        }
+
+        for (i, row) in other.rows.iter().enumerate() {
+            let Some(key) = key_of(row, &other_keys) else {
+                continue;
+            };
+            if matched.contains(&i) || lookup.get(&key) != Some(&i) {
+                continue;
+            }
+
+            unmatched.push(UnmatchedKey {
+                file: other_name.clone(),
+                key: key.join(" | "),
+                missing_from: main_name.clone(),
+            });
+
+            // the key goes in the key columns of the main file, everything else of it is empty
+            if join_type == JoinType::Full {
+                let mut full_row = vec![Cell::Empty; width];
+                for (main_column, other_column) in main_keys.iter().zip(&other_keys) {
+                    full_row[*main_column] = row.get(*other_column).cloned().unwrap_or_default();
+                }
+                full_row.extend(extras(Some(row)));
+                joined.push(full_row);
+            }
+        }
+
+        // a column both files have keeps the name of the main one, the other gets its file's
+        for column in &extra_columns {
+            let name = other.header[*column].clone();
+            let taken = header
+                .iter()
+                .any(|cell| header_key(&cell.as_text()) == header_key(&name.as_text()));
+
+            if taken {
+                header.push(format!("{} ({})", name, other_name).into());
+            } else {
+                header.push(name);
+            }
+        }
+
+        rows = joined;
+    }
+
+    rows.insert(0, header);
+
+    Ok((rows, unmatched))
+}
+
+/// where the key columns are in the header of a file
+fn key_columns(file: &File, keys: &[String]) -> Result<Vec<usize>> {
+    keys.iter()
+        .map(|key| {
+            file.header
+                .iter()
+                .position(|cell| header_key(&cell.as_text()) == header_key(key))
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "{:?} doesn't have a {:?} column",
+                        file.display_name(),
+                        key
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// the values of the key columns of a row, `None` when they're all empty
+fn key_of(row: &[Cell], columns: &[usize]) -> Option<Vec<String>> {
+    let key = columns
+        .iter()
+        .map(|column| {
+            row.get(*column)
+                .map(|cell| cell.as_text().trim().to_string())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    (!key.iter().all(String::is_empty)).then_some(key)
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidJoin {
+        reason: reason.into(),
+    }
+}
+
+/// list the keys that were only in one of the files on a sheet of their own
+pub fn write_unmatched_keys(workbook: &mut Workbook, unmatched: &[UnmatchedKey]) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Unmatched Keys")
+        .context("error setting name of unmatched keys sheet")?;
+
+    for (j, header) in ["File Name", "Key", "Not Found In"].iter().enumerate() {
+        worksheet
+            .write_string(0, j as u16, *header)
+            .context("error writing header")?;
+    }
+
+    for (i, key) in unmatched.iter().enumerate() {
+        let row = (i + 1) as u32;
+
+        for (j, text) in [&key.file, &key.key, &key.missing_from].iter().enumerate() {
+            worksheet
+                .write_string(row, j as u16, text.as_str())
+                .context("error writing unmatched key")?;
+        }
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file;
+
+    /// the main file has the ids 1 and 2, the other one 2 and 3 under a header of another case
+    fn joined(join_type: JoinType) -> (Vec<Vec<Cell>>, Vec<UnmatchedKey>) {
+        let main = test_file("a.xlsx", &["ID", "Name"], &[&["1", "Ali"], &["2", "Mona"]]);
+        let other = test_file("b.xlsx", &["Qty", "id"], &[&["5", "2"], &["7", "3"]]);
+
+        join(main, &[other], &["ID".to_string()], join_type).unwrap()
+    }
+
+    #[test]
+    fn test_left_join() {
+        let (rows, unmatched) = joined(JoinType::Left);
+        assert_eq!(rows[0], vec!["ID".into(), "Name".into(), "Qty".into()]);
+        assert_eq!(rows[1], vec!["1".into(), "Ali".into(), Cell::Empty]);
+        assert_eq!(rows[2], vec!["2".into(), "Mona".into(), "5".into()]);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(unmatched.len(), 2);
+    }
+
+    #[test]
+    fn test_inner_join() {
+        let (rows, _) = joined(JoinType::Inner);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], vec!["2".into(), "Mona".into(), "5".into()]);
+    }
+
+    #[test]
+    fn test_full_join() {
+        let (rows, _) = joined(JoinType::Full);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[3], vec!["3".into(), Cell::Empty, "7".into()]);
+    }
+
+    #[test]
+    fn test_missing_key_column() {
+        let main = test_file("a.xlsx", &["ID", "Name"], &[&["1", "Ali"]]);
+        let other = test_file("b.xlsx", &["Qty", "id"], &[&["5", "2"]]);
+        assert!(join(main, &[other], &["Nope".to_string()], JoinType::Left).is_err());
+    }
+}
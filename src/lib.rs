@@ -1,42 +1,65 @@
 use std::collections::{HashMap, HashSet};
-use std::io::{BufReader, Read, Seek};
 use std::ops::Add;
+use std::path::Path;
 use std::time::Instant;
-use std::{io::Cursor, path::Path};
 
+use crate::align::Alignment;
+use crate::cell::{data_to_cells, Cell, DateFormat};
+use crate::dedup::{Deduplication, KeepPolicy};
 use crate::error::{Error, Result};
+use crate::footer::FooterDetector;
+use crate::formula::{apply_formulas, FormulaMode, MissingValue};
+use crate::header::relative_regions;
+use crate::join::{join, JoinFiles, JoinType};
+use crate::manifest::{FileOptions, FileOptionsForm};
+use crate::mapping::{header_key, ColumnMapping};
 use crate::merge::MergeFiles;
+use crate::normalize::Normalization;
+use crate::provenance::{provenance_cells, Provenance, ProvenanceColumn, RowSource};
+use crate::query::{Query, Score};
 use crate::reply::{MergedLocation, ReplyFile};
+use crate::sort::{sort_cells, sort_files, SortBy, SortKey};
+use crate::style::SheetStyle;
+use crate::summary::FileSummary;
+use crate::upload::{Format, SheetData, Spooler, Upload, UploadLimits};
 
 use anyhow::{anyhow, Context};
 use axum::extract::Multipart;
-use calamine::{Data, Dimensions, Range, Reader, Sheet, Xls, Xlsx};
-use chrono::NaiveDateTime;
+use calamine::{Data, Range, Sheet};
 use itertools::Itertools;
+use rayon::prelude::*;
 use reply::{MergeType, ReplyFiles};
 use search::{Search, SearchFiles};
 use serde::Deserialize;
-use tracing::{debug, info, trace, warn, Instrument};
+use tracing::{debug, info, trace, Instrument};
 use uuid::Uuid;
 
+pub mod align;
 pub mod api;
+pub mod cell;
+pub mod dedup;
+pub mod delimited;
 pub mod error;
+pub mod footer;
+pub mod formula;
+pub mod header;
+pub mod join;
+pub mod manifest;
+pub mod mapping;
+pub mod provenance;
+pub mod query;
 pub mod routes;
 
 pub mod merge;
+pub mod normalize;
 pub mod reply;
 
 pub mod search;
-
-//                 date    files   series  count    name
-type RowNumInfo = (String, String, String, String, String);
-type FileRowNumInfo = Vec<RowNumInfo>;
-
-#[derive(Clone, Debug)]
-enum SortBy {
-    Date,
-    File,
-}
+pub mod sheet;
+pub mod sort;
+pub mod style;
+pub mod summary;
+pub mod upload;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Conditions {
@@ -49,10 +72,29 @@ pub struct Conditions {
 pub struct File {
     pub last_modified: String,
     pub name: String,
+    /// the header row, flattened if it spans several rows
+    pub header: Vec<Cell>,
     /// first is main rows, second is the intro fields
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Vec<Cell>>,
     pub is_main: bool,
     pub id: uuid::Uuid,
+    /// the sheet the rows were read from
+    pub sheet_name: String,
+    /// how many sheets the original workbook had
+    pub sheet_count: usize,
+    /// the size of the uploaded file in bytes
+    pub size: u64,
+    /// how many rows a merge cuts from the top and the bottom of this file
+    pub cut_top: usize,
+    pub cut_bottom: usize,
+    /// the values of the cells the files are sorted by, one for each cell key
+    pub sort_values: Vec<Cell>,
+    /// the zero based sheet row each of the rows was read from
+    pub origins: Vec<usize>,
+    /// the zero based first and last columns of the sheet the rows span
+    pub sheet_columns: (u32, u32),
+    /// the look of the sheet, only read for the main file when it's asked for
+    pub style: Option<SheetStyle>,
 }
 
 impl File {
@@ -60,20 +102,74 @@ impl File {
     pub fn new(
         name: String,
         last_modified: String,
-        rows: Vec<Vec<String>>,
+        rows: Vec<Vec<Cell>>,
         is_main: bool,
         id: Uuid,
+        sheet_name: String,
+        sheet_count: usize,
     ) -> Self {
         File {
             last_modified,
+            header: vec![],
             rows,
             name,
             is_main,
             id,
+            sheet_name,
+            sheet_count,
+            size: 0,
+            sort_values: vec![],
+            cut_top: 0,
+            cut_bottom: 0,
+            origins: vec![],
+            sheet_columns: (0, 0),
+            style: None,
+        }
+    }
+
+    /// drop rows from the top and the bottom, along with where they were read from
+    pub fn cut_rows(&mut self, top: usize, bottom: usize) {
+        let top = top.min(self.rows.len());
+        self.rows.drain(..top);
+        self.origins.drain(..top.min(self.origins.len()));
+
+        let len = self.rows.len().saturating_sub(bottom);
+        self.rows.truncate(len);
+        self.origins.truncate(len);
+    }
+
+    /// the name written to the "File Name" column, includes the sheet name for multi-sheet
+    /// workbooks so rows from different sheets can be told apart
+    pub fn display_name(&self) -> String {
+        if self.sheet_count > 1 {
+            format!("{} [{}]", self.name, self.sheet_name)
+        } else {
+            self.name.clone()
         }
     }
 }
 
+/// a single sheet file with text cells for the tests of the modules working on files
+#[cfg(test)]
+pub(crate) fn test_file(name: &str, header: &[&str], rows: &[&[&str]]) -> File {
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| Cell::from(*cell)).collect())
+        .collect();
+    let mut file = File::new(
+        name.to_string(),
+        "unknown".to_string(),
+        rows,
+        false,
+        Uuid::new_v4(),
+        "Sheet1".to_string(),
+        1,
+    );
+    file.header = header.iter().map(|cell| Cell::from(*cell)).collect();
+
+    file
+}
+
 /// A files map is a struct that represents an map of files to be merged, and some other options
 pub struct FilesMap {
     pub files: Vec<File>,
@@ -83,192 +179,215 @@ pub struct FilesMap {
 }
 
 impl FilesMap {
-    /// sort based on date or file name
-    fn sort(files: &mut [File], sort: SortBy) {
-        match sort {
-            SortBy::Date => {
-                print!("Sorting by date...");
-                files.sort_by(|v1, v2| {
-                    let dt1 =
-                        NaiveDateTime::parse_from_str(&v1.last_modified, "%Y/%m/%d %H:%M").unwrap();
-                    let dt2 =
-                        NaiveDateTime::parse_from_str(&v2.last_modified, "%Y/%m/%d %H:%M").unwrap();
-                    dt1.cmp(&dt2)
-                });
-            }
-
-            SortBy::File => {
-                println!("Sorting by file...");
-                files.sort_by(|b, d| {
-                    let a_name = b
-                        .name
-                        .clone()
-                        .to_ascii_lowercase()
-                        .replace(".xlsx", "")
-                        .replace(".xls", "");
-                    let b_name = d
-                        .name
-                        .clone()
-                        .to_ascii_lowercase()
-                        .replace(".xlsx", "")
-                        .replace(".xls", "");
-                    let a_chars = a_name.chars().collect::<Vec<char>>();
-                    let b_chars = b_name.chars().collect::<Vec<char>>();
-                    let mut i = 0;
-                    while i < a_chars.len() && i < b_chars.len() {
-                        let a_char = a_chars[i];
-                        let b_char = b_chars[i];
-                        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
-                            let a_num = a_char.to_digit(10).unwrap();
-                            let b_num = b_char.to_digit(10).unwrap();
-                            if a_num != b_num {
-                                return a_num.cmp(&b_num);
-                            }
-                        } else if a_char != b_char {
-                            return a_char.cmp(&b_char);
-                        }
-                        i += 1;
-                    }
-                    a_chars.len().cmp(&b_chars.len())
-                });
-            }
-        }
-    }
-
     /// merge files
-    pub async fn merge_from_multipart(mut multipart: Multipart) -> Result<MergeFiles> {
-        let mut files: Vec<File> = vec![];
-        let mut dates: Vec<String> = vec![];
-        let mut first_rows: Vec<String> = vec![];
+    pub async fn merge_from_multipart(
+        mut multipart: Multipart,
+        limits: UploadLimits,
+    ) -> Result<MergeFiles> {
+        let mut uploads: Vec<Upload> = vec![];
+        let mut spooler = Spooler::new(limits);
+        let mut options = FileOptionsForm::default();
+        let mut date_format = DateFormat::default();
+        let mut formulas = FormulaMode::default();
+        let mut mapping: Option<ColumnMapping> = None;
+        let mut provenance = ProvenanceColumn::defaults();
 
         let mut cutting_rows: usize = 0;
         let mut sort_by_date: bool = false;
         let mut sort_by_file: bool = false;
+        let mut sort_keys: Vec<SortBy> = vec![];
+        let mut align_headers: bool = false;
+        let mut dedup = Deduplication::default();
+        let mut trim_footer: bool = false;
+        let mut keep_styles: bool = false;
+        let mut summary: bool = false;
+        let mut footer_patterns: Vec<String> = vec![];
+
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .context("error reading the form")?
+        {
+            let name = field.name().unwrap_or("unknown").to_owned();
+            // only file inputs have a file name, an empty one is an input with nothing selected
+            let other_name = field
+                .file_name()
+                .filter(|file_name| !file_name.is_empty())
+                .map(str::to_owned);
 
-        while let Some(field) = multipart.next_field().await.unwrap() {
-            let content_type = field.content_type().map(str::to_owned);
+            if let Some(other_name) = other_name {
+                debug!("File name (excel): {:?}", &name);
+                let upload = spooler.spool(field, name, other_name).await?;
+
+                // the mapping is uploaded along with the files, but isn't one of them
+                if upload.field_name == "mapping" {
+                    mapping = Some(ColumnMapping::from_upload(&upload)?);
+                } else {
+                    uploads.push(upload);
+                }
+
+                continue;
+            }
 
-            let name = field.name().unwrap_or("unknown").to_owned();
-            let other_name = field.file_name().unwrap_or("unknown").to_owned();
             let bytes = field.bytes().await.unwrap();
 
+            if name == "mapping" {
+                mapping = Some(ColumnMapping::from_json(&bytes)?);
+
+                continue;
+            }
+
             if name.starts_with("sort-by") {
-                if name == "sort-by-date" {
-                    let val = String::from_utf8(bytes.to_vec())
-                        .unwrap()
-                        .parse::<bool>()
-                        .unwrap();
+                let val = parse_flag(&name, &bytes)?;
 
-                    if val {
-                        sort_by_date = true;
-                    }
+                if name == "sort-by-date" {
+                    sort_by_date |= val;
                 } else if name == "sort-by-file" {
-                    let val = String::from_utf8(bytes.to_vec())
-                        .unwrap()
-                        .parse::<bool>()
-                        .unwrap();
-
-                    if val {
-                        sort_by_file = true;
-                    }
+                    sort_by_file |= val;
                 }
                 continue;
             }
 
-            if name == "last-mod[]" {
-                let date = String::from_utf8(bytes.to_vec()).unwrap();
+            if name == "sort" {
+                sort_keys = SortBy::parse_list(&bytes)?;
 
-                dates.push(date);
+                continue;
+            }
 
+            if options.read_field(&name, &bytes)? {
                 continue;
             }
 
-            if name == "cuttingRows" {
-                let cutting_rows_str = String::from_utf8(bytes.to_vec()).unwrap();
-                println!("Cutting rows (str): {}", &cutting_rows_str);
+            if name == "date-format" {
+                date_format = DateFormat::parse(&String::from_utf8_lossy(&bytes))?;
 
-                if !cutting_rows_str.is_empty() {
-                    cutting_rows = cutting_rows_str.parse::<usize>().unwrap();
-                    println!("Cutting rows (usize): {}", &cutting_rows);
+                continue;
+            }
+
+            if name == "provenance" {
+                provenance = ProvenanceColumn::parse_list(&bytes)?;
+
+                continue;
+            }
+
+            if name == "formulas" {
+                formulas = FormulaMode::parse(&String::from_utf8_lossy(&bytes))?;
+
+                continue;
+            }
+
+            if name == "align-headers" {
+                align_headers = parse_flag(&name, &bytes)?;
+
+                continue;
+            }
+
+            if name == "keep-styles" {
+                keep_styles = parse_flag(&name, &bytes)?;
+
+                continue;
+            }
+
+            if name == "summary" {
+                summary = parse_flag(&name, &bytes)?;
+
+                continue;
+            }
+
+            if name == "dedup-key[]" {
+                let key = String::from_utf8_lossy(&bytes).trim().to_string();
+                if !key.is_empty() {
+                    dedup.keys.push(key);
                 }
 
                 continue;
             }
 
-            if content_type
-                == Some(
-                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".to_string(),
-                )
-                || content_type == Some("application/vnd.ms-excel".to_string())
-            {
-                let bytes = bytes.to_vec();
+            if name == "dedup-keep" {
+                dedup.keep = KeepPolicy::parse(&String::from_utf8_lossy(&bytes))?;
 
-                let is_main = name == "main-file";
+                continue;
+            }
 
-                let reader = Cursor::new(bytes);
-                let mut workbook = calamine::open_workbook_auto_from_rs(reader).unwrap();
+            if name == "trim-footer" {
+                trim_footer = parse_flag(&name, &bytes)?;
 
-                println!("File name (excel): {:?}", &name);
+                continue;
+            }
 
-                if workbook.worksheets().len() > 1 {
-                    return Err(Error::SheetLimitExceeded);
+            if name == "footer-pattern[]" {
+                let pattern = String::from_utf8_lossy(&bytes).trim().to_string();
+                if !pattern.is_empty() {
+                    footer_patterns.push(pattern);
                 }
 
-                if let Some(range) = workbook.worksheet_range_at(0) {
-                    let sheet = range.unwrap();
-                    let rows: Vec<Vec<String>> = sheet
-                        .rows()
-                        .map(|row| {
-                            row.iter()
-                                .map(|cell| match cell {
-                                    Data::String(s) => s.to_owned(),
-                                    Data::Float(s) => s.to_string(),
-                                    Data::Int(s) => s.to_string(),
-                                    Data::DateTime(s) => s.to_string(),
-                                    Data::DateTimeIso(s) => s.to_string(),
-                                    Data::Empty => "".to_string(),
-                                    _ => "unknown".to_owned(),
-                                })
-                                .collect_vec()
-                        })
-                        .collect();
-
-                    if is_main {
-                        first_rows = rows[0].clone();
-                    }
+                continue;
+            }
 
-                    files.push(File::new(
-                        other_name,
-                        "unknown".to_string(),
-                        rows,
-                        is_main,
-                        Uuid::new_v4(),
-                    ));
+            if name == "cuttingRows" {
+                let cutting_rows_str = String::from_utf8(bytes.to_vec()).unwrap();
+                println!("Cutting rows (str): {}", &cutting_rows_str);
+
+                if !cutting_rows_str.is_empty() {
+                    cutting_rows = cutting_rows_str.parse::<usize>().unwrap();
+                    println!("Cutting rows (usize): {}", &cutting_rows);
                 }
 
                 continue;
             }
         }
 
-        files.sort_by_key(|file| !file.is_main);
+        // the main file's options always come first
+        uploads.sort_by_key(|upload| !upload.is_main());
 
-        files.iter_mut().enumerate().for_each(|(i, file)| {
-            file.last_modified = dates[i].clone();
-        });
+        let file_names = uploads
+            .iter()
+            .map(|upload| upload.file_name.as_str())
+            .collect_vec();
+        let options = options.resolve(&file_names)?;
+        let uploads = spooler.expand(uploads, options).await?;
+
+        // the older flags are a single key each, the date winning over the name
+        if sort_keys.is_empty() {
+            if sort_by_date {
+                sort_keys.push(SortBy::ascending(SortKey::Date));
+            } else if sort_by_file {
+                sort_keys.push(SortBy::ascending(SortKey::Name));
+            }
+        }
+
+        let (mut files, missing_values) =
+            parse_uploads(uploads, formulas, sort_cells(&sort_keys), keep_styles).await?;
 
-        dates.clear();
+        // rename the headers before anything looks at them
+        let unmapped_headers = match &mapping {
+            Some(mapping) => mapping.apply(&mut files),
+            None => vec![],
+        };
+
+        let mut first_rows = files
+            .iter()
+            .find(|file| file.is_main && !file.header.is_empty())
+            .map(|file| file.header.clone())
+            .unwrap_or_default();
 
         println!("Files: {:?}", &files);
 
-        if sort_by_date {
-            FilesMap::sort(&mut files, SortBy::Date);
-        } else if sort_by_file {
-            FilesMap::sort(&mut files, SortBy::File);
-        }
+        sort_files(&mut files, &sort_keys)?;
 
         // sorting that will run anyways
         files.sort_by_key(|file| !file.is_main);
 
+        // the files keep this order from here on, their summaries follow them along
+        let mut summaries = if summary {
+            files
+                .iter()
+                .map(|file| FileSummary::new(file, &first_rows))
+                .collect_vec()
+        } else {
+            vec![]
+        };
+
         files.iter().for_each(|v| {
             println!(
                 "Name: {:?}, rows: {:?}, is_main: {:?}, date_modified: {:?}",
@@ -294,31 +413,95 @@ impl FilesMap {
         // cut n rows from each non-main file
         if cutting_rows > 0 {
             files.iter_mut().filter(|x| !x.is_main).for_each(|v| {
-                v.rows = v.rows.drain((cutting_rows - 1)..).collect();
+                v.cut_rows(cutting_rows - 1, 0);
             });
         }
 
+        // the cuts of each file, then whatever footer is still left under its data
+        let footer = trim_footer.then(|| {
+            if footer_patterns.is_empty() {
+                FooterDetector::default()
+            } else {
+                FooterDetector {
+                    patterns: footer_patterns,
+                }
+            }
+        });
+        for file in files.iter_mut() {
+            file.cut_rows(file.cut_top, file.cut_bottom);
+
+            if let Some(footer) = &footer {
+                let footer_len = footer.footer_len(&file.rows, file.header.len());
+                debug!("Trimming {} footer rows from {:?}", footer_len, file.name);
+
+                file.cut_rows(0, footer_len);
+            }
+        }
+        for (summary, file) in summaries.iter_mut().zip(&files) {
+            summary.rows_cut = summary.rows_read - file.rows.len();
+        }
+
+        // line the columns up by their header instead of their position, the headers are the
+        // union of all of them with the main file's first
+        let mut column_differences = vec![];
+        if align_headers {
+            let names = files
+                .iter()
+                .map(|file| file.display_name().replace("-MAIN", ""))
+                .collect_vec();
+            let headers = files
+                .iter()
+                .zip(&names)
+                .map(|(file, name)| (name.as_str(), file.header.as_slice()))
+                .collect_vec();
+            let alignment = Alignment::new(&headers);
+
+            for (i, file) in files.iter_mut().enumerate() {
+                file.rows = std::mem::take(&mut file.rows)
+                    .into_iter()
+                    .map(|row| alignment.realign(i, row))
+                    .collect();
+                file.header = alignment.header.clone();
+            }
+
+            first_rows = alignment.header;
+            column_differences = alignment.differences;
+        }
+
+        // drop the rows repeating the key of another one, once the files are in their merged order
+        let duplicates = if dedup.keys.is_empty() {
+            vec![]
+        } else {
+            dedup.apply(&mut files)?
+        };
+        for (summary, file) in summaries.iter_mut().zip(&files) {
+            summary.finish(file, &missing_values, &unmapped_headers, &duplicates);
+        }
+
         let mut acc_width = 0;
-        let mut values_rows: Vec<Vec<String>> = files
+        let mut values_rows: Vec<Vec<Cell>> = files
             .iter()
             .enumerate()
             .flat_map(|(i, inner_vec)| {
-                let main_data: Vec<Vec<String>> = inner_vec
+                let main_data: Vec<Vec<Cell>> = inner_vec
                     .rows
                     .iter()
-                    .skip(1)
                     .enumerate()
                     .map(|(j, file)| {
-                        let mut intro_headers: Vec<String> = vec![];
-                        let cur_row_values: Vec<String> =
+                        let cur_row_values: Vec<Cell> =
                             file.iter().map(|row_data| row_data.to_owned()).collect();
 
-                        intro_headers.push(inner_vec.last_modified.to_owned());
-                        intro_headers.push((i + 1).to_string());
-                        intro_headers.push((acc_width + 1).to_string());
-                        intro_headers
-                            .push((i + 1).to_string() + "-" + ((j) + 1).to_string().as_str());
-                        intro_headers.push(inner_vec.name.replace("-MAIN", ""));
+                        let intro_headers = provenance_cells(
+                            &provenance,
+                            &RowSource {
+                                file: inner_vec,
+                                file_number: i + 1,
+                                series: acc_width + 1,
+                                count: format!("{}-{}", i + 1, j + 1),
+                                index: j,
+                                score: None,
+                            },
+                        );
 
                         acc_width += 1;
 
@@ -331,181 +514,96 @@ impl FilesMap {
             .collect();
 
         // modify the headers
-        let mut extra_headers = [
-            "Date Modified",
-            "Number of Files",
-            "Series Number",
-            "Count Number",
-            "File Name",
-        ]
-        .iter()
-        .map(|x| x.to_string())
-        .collect::<Vec<String>>();
+        let mut extra_headers = provenance
+            .iter()
+            .map(|column| Cell::from(column.name.as_str()))
+            .collect::<Vec<Cell>>();
 
         extra_headers.append(&mut first_rows);
         values_rows.insert(0, extra_headers);
 
-        Ok(MergeFiles { rows: values_rows })
+        let style = files
+            .iter()
+            .find(|file| file.is_main)
+            .and_then(|file| file.style.clone());
+
+        Ok(MergeFiles {
+            rows: values_rows,
+            date_format,
+            missing_values,
+            column_differences,
+            unmapped_headers,
+            duplicates,
+            provenance,
+            style,
+            summaries,
+        })
     }
 
-    // TODO: block workboots with more than a sheet!
-    pub async fn reply_from_multipart(mut multipart: Multipart) -> Result<ReplyFiles> {
+    pub async fn reply_from_multipart(
+        mut multipart: Multipart,
+        limits: UploadLimits,
+    ) -> Result<ReplyFiles> {
         let mut files: ReplyFiles = ReplyFiles::new(vec![]);
-        let mut dates: Vec<String> = vec![];
-        let mut rename: Vec<bool> = vec![];
-        let mut cutting_rows: Vec<u32> = vec![];
-        let mut sizes: Vec<u32> = vec![];
-        let mut checked: Vec<bool> = vec![];
-        let mut reply: Vec<bool> = vec![];
-
-        while let Some(field) = multipart.next_field().await.unwrap() {
-            let content_type = field.content_type().map(str::to_owned);
-
+        let mut uploads: Vec<Upload> = vec![];
+        let mut spooler = Spooler::new(limits);
+        let mut options = FileOptionsForm::default();
+        let mut date_format = DateFormat::default();
+        let mut formulas = FormulaMode::default();
+
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .context("error reading the form")?
+        {
             let name = field.name().unwrap_or("unknown").to_owned();
-            let other_name = field.file_name().unwrap_or("unknown").to_owned();
-            let bytes = field.bytes().await.unwrap();
-
-            if name == "last-mod[]" {
-                let date = String::from_utf8(bytes.to_vec()).unwrap();
-
-                dates.push(date);
-
-                continue;
-            }
-
-            if name == "checked[]" {
-                if let Ok(checked_str) = String::from_utf8(bytes.to_vec()) {
-                    if let Ok(checked_value) = checked_str.parse::<bool>() {
-                        checked.push(checked_value);
-                    }
-                }
-                trace!("checked: {:?}", checked);
-
-                continue;
-            }
-
-            if name == "reply[]" {
-                if let Ok(reply_str) = String::from_utf8(bytes.to_vec()) {
-                    if let Ok(reply_value) = reply_str.parse::<bool>() {
-                        reply.push(reply_value);
-                    }
-                }
-                trace!("reply: {:?}", reply);
-
-                continue;
-            }
+            // only file inputs have a file name, an empty one is an input with nothing selected
+            let other_name = field
+                .file_name()
+                .filter(|file_name| !file_name.is_empty())
+                .map(str::to_owned);
 
-            if name == "cut-row[]" {
-                if let Ok(cut_row_str) = String::from_utf8(bytes.to_vec()) {
-                    if cut_row_str.is_empty() {
-                        cutting_rows.push(0);
-                    }
-                    if let Ok(cut_row) = cut_row_str.parse::<u32>() {
-                        cutting_rows.push(cut_row);
-                    }
-                }
-                trace!("Cutting-rows: {:?}", cutting_rows);
+            if let Some(other_name) = other_name {
+                debug!("File name (excel): {:?}", &name);
+                uploads.push(spooler.spool(field, name, other_name).await?);
 
                 continue;
             }
 
-            if name == "rename[]" {
-                if let Ok(rename_str) = String::from_utf8(bytes.to_vec()) {
-                    if let Ok(rename_value) = rename_str.parse::<bool>() {
-                        rename.push(rename_value);
-                    }
-                }
-                trace!("Rename: {:?}", rename);
+            let bytes = field.bytes().await.unwrap();
 
+            if options.read_field(&name, &bytes)? {
                 continue;
             }
 
-            if name == "size[]" {
-                if let Ok(size_str) = String::from_utf8(bytes.to_vec()) {
-                    if let Ok(size) = size_str.parse::<u32>() {
-                        sizes.push(size);
-                    }
-                }
-                trace!("Size values: {:?}", sizes);
+            if name == "date-format" {
+                date_format = DateFormat::parse(&String::from_utf8_lossy(&bytes))?;
 
                 continue;
             }
 
-            if let Some(content_type) = content_type {
-                let bytes = bytes.to_vec();
-                let reader = Cursor::new(bytes);
-
-                match content_type.as_str() {
-                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
-                        let mut workbook: calamine::Xlsx<_> =
-                            calamine::open_workbook_from_rs(reader).unwrap();
+            if name == "formulas" {
+                formulas = FormulaMode::parse(&String::from_utf8_lossy(&bytes))?;
 
-                        if workbook.worksheets().len() > 1 {
-                            warn!("Has more than one sheet! Will only parse the first sheet.");
-                            return Err(Error::SheetLimitExceeded);
-                        } else {
-                            debug!("Has only one sheet.")
-                        }
-                        println!("File name (xlsx): {:?}", &name);
-                        let mut merged_regions: Vec<Dimensions> = vec![];
-                        if workbook.load_merged_regions().is_ok() {
-                            // FIXME: don't use to_owned
-                            merged_regions = workbook
-                                .merged_regions()
-                                .to_owned()
-                                .iter()
-                                .map(|region| region.2)
-                                .collect();
-                            trace!("Merged regions: {:?}", merged_regions);
-                        }
-                        process_workbook(&mut workbook, &other_name, &mut files, &merged_regions);
-                    }
-                    "application/vnd.ms-excel" => {
-                        let mut workbook: calamine::Xls<_> =
-                            calamine::open_workbook_from_rs(reader).unwrap();
-
-                        if workbook.worksheets().len() > 1 {
-                            warn!("Has more than one sheet! Will only parse the first sheet.");
-                            return Err(Error::SheetLimitExceeded);
-                        } else {
-                            debug!("Has only one sheet.")
-                        }
-                        let mut merged_regions: Vec<Dimensions> = vec![];
-                        println!("File name (xls): {:?}", &name);
-                        if let Some(x) = workbook.worksheet_merge_cells_at(0) {
-                            merged_regions = x;
-                        }
-                        process_workbook(&mut workbook, &other_name, &mut files, &merged_regions);
-                    }
-                    _ => {
-                        // Handle other content types or errors
-                    }
-                }
                 continue;
             }
         }
 
-        files.data.iter_mut().enumerate().for_each(|(i, file)| {
-            file.last_modified = dates[i].clone();
-            file.cutting_rows = cutting_rows[i].clone();
-            file.size = sizes[i].clone();
-            file.rename = rename[i].clone();
-            file.checked = checked[i].clone();
-            file.reply = reply[i].clone();
-        });
+        let file_names = uploads
+            .iter()
+            .map(|upload| upload.file_name.as_str())
+            .collect_vec();
+        let options = options.resolve(&file_names)?;
+        let uploads = spooler.expand(uploads, options).await?;
 
-        dates.clear();
-        cutting_rows.clear();
-        sizes.clear();
-        rename.clear();
-        checked.clear();
-        reply.clear();
+        files
+            .data
+            .extend(read_reply_uploads(uploads, formulas).await?);
 
         // dbg!(&files.data);
 
         files.data.retain(|file| file.checked == true);
 
-        // assumption: there's only one sheet
         for file in &mut files.data {
             // do the cut
             let original_rows = file.rows.clone();
@@ -617,72 +715,173 @@ impl FilesMap {
             }
         }
 
+        files.date_format = date_format;
+
         Ok(files)
     }
 
-    /// search and filter out the matched rows
-    pub async fn search_from_multipart(mut multipart: Multipart) -> Result<SearchFiles> {
-        let mut files: Vec<File> = vec![];
-        let mut dates: Vec<String> = vec![];
-        let mut conditions: Conditions = Conditions { conditions: vec![] };
+    /// join the other files to the main file on key columns, like a VLOOKUP
+    pub async fn join_from_multipart(
+        mut multipart: Multipart,
+        limits: UploadLimits,
+    ) -> Result<JoinFiles> {
+        let mut uploads: Vec<Upload> = vec![];
+        let mut spooler = Spooler::new(limits);
+        let mut options = FileOptionsForm::default();
+        let mut date_format = DateFormat::default();
+        let mut keys: Vec<String> = vec![];
+        let mut join_type = JoinType::default();
+
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .context("error reading the form")?
+        {
+            let name = field.name().unwrap_or("unknown").to_owned();
+            // only file inputs have a file name, an empty one is an input with nothing selected
+            let other_name = field
+                .file_name()
+                .filter(|file_name| !file_name.is_empty())
+                .map(str::to_owned);
 
-        // fetch the results from the multipart form
-        while let Some(field) = multipart.next_field().await.unwrap() {
-            let content_type = field.content_type().map(str::to_owned);
+            if let Some(other_name) = other_name {
+                debug!("File name (excel): {:?}", &name);
+                uploads.push(spooler.spool(field, name, other_name).await?);
+
+                continue;
+            }
 
-            let name = field.name().unwrap_or("unknown").to_owned();
-            let other_name = field.file_name().unwrap_or("unknown").to_owned();
             let bytes = field.bytes().await.unwrap();
 
-            if name == "last-mod[]" {
-                let date = String::from_utf8(bytes.to_vec()).context("error parsing date")?;
+            if options.read_field(&name, &bytes)? {
+                continue;
+            }
+
+            if name == "date-format" {
+                date_format = DateFormat::parse(&String::from_utf8_lossy(&bytes))?;
 
-                dates.push(date);
                 continue;
             }
 
-            if content_type
-                == Some(
-                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".to_string(),
-                )
-                || content_type == Some("application/vnd.ms-excel".to_string())
-            {
-                let bytes = bytes.to_vec();
-                let reader = Cursor::new(bytes);
-                // TODO: we know the type, so use the static alternative from calamine
-                let mut workbook = calamine::open_workbook_auto_from_rs(reader)
-                    .context("error opening workbook")?;
-
-                // let mut workbook: Xls<Cursor<Vec<u8>>> =
-                //     calamine::open_workbook_from_rs(reader).context("error opening workbook")?;
+            if name == "join-key[]" {
+                let key = String::from_utf8_lossy(&bytes).trim().to_string();
+                if !key.is_empty() {
+                    keys.push(key);
+                }
 
+                continue;
+            }
+
+            if name == "join-type" {
+                join_type = JoinType::parse(&String::from_utf8_lossy(&bytes))?;
+
+                continue;
+            }
+        }
+
+        if keys.is_empty() {
+            return Err(Error::InvalidJoin {
+                reason: "no key columns were chosen".to_string(),
+            });
+        }
+
+        // the main file's options always come first
+        uploads.sort_by_key(|upload| !upload.is_main());
+
+        let file_names = uploads
+            .iter()
+            .map(|upload| upload.file_name.as_str())
+            .collect_vec();
+        let options = options.resolve(&file_names)?;
+        let uploads = spooler.expand(uploads, options).await?;
+
+        let (mut files, _) = parse_uploads(uploads, FormulaMode::Cached, vec![], false).await?;
+
+        // the first sheet of the main file is the left table, everything else is joined to it
+        let main = match files.iter().position(|file| file.is_main) {
+            Some(i) => files.remove(i),
+            None => {
+                return Err(Error::InvalidJoin {
+                    reason: "a main file is needed to join the other files to".to_string(),
+                })
+            }
+        };
+
+        let (rows, unmatched) =
+            tokio::task::spawn_blocking(move || join(main, &files, &keys, join_type))
+                .await
+                .context("error joining the files")??;
+
+        Ok(JoinFiles {
+            rows,
+            date_format,
+            unmatched,
+        })
+    }
+
+    /// search and filter out the matched rows
+    pub async fn search_from_multipart(
+        mut multipart: Multipart,
+        limits: UploadLimits,
+    ) -> Result<SearchFiles> {
+        let mut uploads: Vec<Upload> = vec![];
+        let mut spooler = Spooler::new(limits);
+        let mut options = FileOptionsForm::default();
+        let mut date_format = DateFormat::default();
+        let mut conditions: Conditions = Conditions { conditions: vec![] };
+        let mut query: Option<Query> = None;
+        let mut normalization = Normalization::default();
+        let mut sort_by_score = false;
+        let mut mapping: Option<ColumnMapping> = None;
+        let mut provenance = ProvenanceColumn::defaults();
+
+        // fetch the results from the multipart form
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .context("error reading the form")?
+        {
+            let name = field.name().unwrap_or("unknown").to_owned();
+            // only file inputs have a file name, an empty one is an input with nothing selected
+            let other_name = field
+                .file_name()
+                .filter(|file_name| !file_name.is_empty())
+                .map(str::to_owned);
+
+            if let Some(other_name) = other_name {
                 debug!("File name (excel): {:?}", &name);
+                let upload = spooler.spool(field, name, other_name).await?;
 
-                if workbook.worksheets().len() > 1 {
-                    warn!("Has more than one sheet! Will only parse the first sheet.");
+                // the mapping is uploaded along with the files, but isn't one of them
+                if upload.field_name == "mapping" {
+                    mapping = Some(ColumnMapping::from_upload(&upload)?);
                 } else {
-                    debug!("Has only one sheet.")
+                    uploads.push(upload);
                 }
 
-                debug!("Parsing a single-sheet file");
+                continue;
+            }
+
+            let bytes = field.bytes().await.unwrap();
 
-                if let Some(range) = workbook.worksheet_range_at(0) {
-                    let sheet = range.context("error getting range")?;
+            if name == "mapping" {
+                mapping = Some(ColumnMapping::from_json(&bytes)?);
 
-                    let rows = sheet_to_rows(sheet);
+                continue;
+            }
 
-                    info!("Parsing rows");
+            if options.read_field(&name, &bytes)? {
+                continue;
+            }
 
-                    info!("Finished. Pushing the file");
+            if name == "date-format" {
+                date_format = DateFormat::parse(&String::from_utf8_lossy(&bytes))?;
 
-                    files.push(File::new(
-                        other_name,
-                        "unknown".to_string(),
-                        rows,
-                        false,
-                        Uuid::new_v4(),
-                    ));
-                }
+                continue;
+            }
+
+            if name == "provenance" {
+                provenance = ProvenanceColumn::parse_list(&bytes)?;
 
                 continue;
             }
@@ -695,19 +894,59 @@ impl FilesMap {
 
                 continue;
             }
+
+            if name == "query" {
+                query = Some(Query::parse(&String::from_utf8_lossy(&bytes))?);
+
+                debug!("Query: {:?}", &query);
+
+                continue;
+            }
+
+            if name == "normalize" {
+                normalization = Normalization::parse_list(&bytes)?;
+
+                debug!("Normalization: {:?}", &normalization);
+
+                continue;
+            }
+
+            if name == "sort-by-score" {
+                sort_by_score = parse_flag(&name, &bytes)?;
+
+                continue;
+            }
         }
 
+        // the older conditions are a query too, a query of its own is used instead of them
+        let mut query = match query {
+            Some(query) => query,
+            None => Query::from_conditions(&conditions.conditions)?,
+        };
+
+        info!("Parsing files.");
+
+        let file_names = uploads
+            .iter()
+            .map(|upload| upload.file_name.as_str())
+            .collect_vec();
+        let options = options.resolve(&file_names)?;
+        let uploads = spooler.expand(uploads, options).await?;
+
+        let (mut files, _) = parse_uploads(uploads, FormulaMode::Cached, vec![], false).await?;
+
         if files.is_empty() {
             return Err(Error::Other(anyhow!("No files were found.")));
         }
 
-        info!("Setting dates.");
-        // set the right dates, index based
-        files.iter_mut().enumerate().for_each(|(i, file)| {
-            file.last_modified = dates[i].clone();
-        });
-
-        dates.clear();
+        // the columns are matched against the canonical names, both sides have to use them
+        let unmapped_headers = match &mapping {
+            Some(mapping) => {
+                mapping.apply_to_query(&mut query);
+                mapping.apply(&mut files)
+            }
+            None => vec![],
+        };
 
         files.iter().for_each(|v| {
             trace!(
@@ -721,7 +960,27 @@ impl FilesMap {
 
         info!("Merging files...");
 
-        let filtered_rows = search_from_files(&files, &conditions);
+        // the cells are normalized as they're searched, the query only once
+        query.normalize(&normalization)?;
+
+        // a fuzzy search always says how close it came, under the names sent for them if any
+        if query.is_fuzzy() {
+            for kind in [Provenance::Score, Provenance::Variant] {
+                if !provenance.iter().any(|column| column.kind == kind) {
+                    provenance.push(ProvenanceColumn::new(kind));
+                }
+            }
+        }
+
+        let (filtered_rows, query, normalization, provenance) =
+            tokio::task::spawn_blocking(move || {
+                let filtered_rows =
+                    search_from_files(&files, &query, &normalization, &provenance, sort_by_score);
+
+                (filtered_rows, query, normalization, provenance)
+            })
+            .await
+            .context("error searching the files")?;
 
         let total_rows = filtered_rows.0.len();
         info!("Total rows: {:?}", total_rows);
@@ -730,139 +989,149 @@ impl FilesMap {
 
         Ok(SearchFiles {
             rows: filtered_rows,
-            conditions: conditions.conditions,
+            query,
+            normalization,
+            date_format,
+            unmapped_headers,
+            provenance,
         })
     }
 }
 
-fn search_from_files(files: &[File], conditions: &Conditions) -> (Vec<Vec<String>>, Vec<String>) {
-    let mut filtered_files: Vec<File> = vec![];
-    let mut headers: Vec<String> = vec![];
-    let mut filtered_files_title_bars: Vec<(usize, Vec<String>)> = vec![];
-
-    let mut total_rows_count = 0;
-    let mut total_matched_files_count = 0;
-
-    info!("Start searching.");
-
-    let info = calc_file_row_num_infos(files);
+/// the matches of a single file, the running counters are filled in once every file is searched
+struct FileMatches {
+    points: usize,
+    headers: Vec<String>,
+    /// the index of the matched row in the file, along with its cells and how close the fuzzy
+    /// terms came to it
+    rows: Vec<(usize, Vec<Cell>, Option<Score>)>,
+}
 
-    for (i, file) in files.iter().enumerate() {
-        let instant = Instant::now();
-        let mut is_matched;
-        let mut new_file_rows: Vec<Vec<String>> = vec![];
-        let mut points: usize = 0;
+fn search_file(file: &File, query: &Query, normalization: &Normalization) -> FileMatches {
+    let instant = Instant::now();
+    let headers = header_titles(&file.header);
+    let header_keys = headers
+        .iter()
+        .map(|title| header_key(&normalization.apply(title).text))
+        .collect_vec();
 
-        for search in &conditions.conditions {
-            let current_file_rows = &file.rows;
+    let rows = file
+        .rows
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cells)| {
+            // match against the text of the cells, the typed cells are what we write back
+            let originals = cells.iter().map(Cell::as_text).collect_vec();
+            let normalized = originals
+                .iter()
+                .map(|cell| normalization.apply(cell))
+                .collect_vec();
+            let row = normalized
+                .iter()
+                .map(|cell| cell.text.as_str())
+                .collect_vec();
 
-            headers = current_file_rows.first().unwrap().clone();
+            if !query.matches(&row, cells, &header_keys) {
+                return None;
+            }
 
-            for (j, row) in current_file_rows.iter().enumerate() {
-                let filtered_row = row
+            // the variants are the text the cells had, not the normalized one
+            let score = query.fuzzy_match(&row, &header_keys).map(|found| {
+                let variant = found
+                    .variants
                     .iter()
-                    .filter(|x| x.contains(&search.data))
-                    .collect_vec();
+                    .map(|(k, span)| &originals[*k][normalized[*k].original(span)])
+                    .join(" | ");
 
-                if filtered_row.is_empty() {
-                    // if there are even no matches, then skip the next row
-                    continue;
+                Score {
+                    score: found.score,
+                    variant,
                 }
+            });
 
-                let dups = row
-                    .iter()
-                    .duplicates()
-                    .filter(|x| **x == search.data)
-                    .collect_vec();
-
-                let index = row.iter().position(|x| x.contains(&search.data)).unwrap();
-
-                // data is matched, check the title
-                is_matched = true;
+            Some((j, cells.clone(), score))
+        })
+        .collect_vec();
 
-                // title
-                if let Some(title) = &search.title {
-                    if !title.is_empty() {
-                        is_matched = headers[index] == *title;
-                        // if the title doesn't match, then skip to the next cell, it's not what we want
-                        if !is_matched {
-                            continue;
-                        }
-                    }
-                }
+    debug!("iteration duration: {:?}", instant.elapsed());
 
-                // intersections
-                if is_matched && !search.intersections.is_empty() {
-                    search.intersections.iter().for_each(|search| {
-                        if row.contains(&search.data) {
-                            let index = row.iter().position(|x| x == &search.data).unwrap();
-
-                            if let Some(title) = &search.title {
-                                is_matched = headers[index] == *title;
-                            }
-                        } else {
-                            is_matched = false;
-                        }
-                    })
-                }
+    FileMatches {
+        points: rows.len(),
+        headers,
+        rows,
+    }
+}
 
-                // points handling
-                // if we have duplicates, then add a point only for those whose title matches the
-                // query title
-                if !dups.is_empty() {
-                    let idxs = find_dup_indices(dups[0], row);
-                    for idx in idxs {
-                        if let Some(title) = &search.title {
-                            if !title.is_empty() {
-                                if headers[idx] == *title {
-                                    points += 1;
-                                }
-                            } else {
-                                points += 1;
-                            }
-                        }
-                    }
-                } else {
-                    // otherwise, add a single point
-                    points += 1
-                }
+fn search_from_files(
+    files: &[File],
+    query: &Query,
+    normalization: &Normalization,
+    provenance: &[ProvenanceColumn],
+    sort_by_score: bool,
+) -> (Vec<Vec<Cell>>, Vec<String>) {
+    let mut filtered_files: Vec<File> = vec![];
+    let mut filtered_files_title_bars: Vec<(usize, Vec<String>)> = vec![];
 
-                // we push the row if it's matched
-                if is_matched {
-                    let row_num_info = info.get(i).unwrap().get(j).unwrap();
+    let mut total_rows_count = 0;
+    let mut total_matched_files_count = 0;
 
-                    let mut new_row = vec![
-                        row_num_info.0.clone(),
-                        (total_matched_files_count + 1).to_string(),
-                        (total_rows_count + 1).to_string(),
-                        row_num_info.3.to_string(),
-                        row_num_info.4.clone(),
-                    ];
+    info!("Start searching.");
 
-                    new_row.extend_from_slice(row);
+    // search the files in parallel, the results are still in the order of the files, so the
+    // counters below come out the same every time
+    let matches: Vec<FileMatches> = files
+        .par_iter()
+        .map(|file| search_file(file, query, normalization))
+        .collect();
 
-                    new_file_rows.push(new_row);
+    for (i, (file, matches)) in files.iter().zip(matches).enumerate() {
+        let new_file_rows = matches
+            .rows
+            .into_iter()
+            .map(|(j, cells, score)| {
+                let mut new_row = provenance_cells(
+                    provenance,
+                    &RowSource {
+                        file,
+                        file_number: total_matched_files_count + 1,
+                        series: total_rows_count + 1,
+                        count: format!("{}-{}", i + 1, j + 1),
+                        index: j,
+                        score,
+                    },
+                );
+
+                new_row.extend(cells);
+                total_rows_count += 1;
 
-                    total_rows_count += 1;
-                }
-            }
-        }
+                new_row
+            })
+            .collect_vec();
 
-        if points > 0 {
+        if matches.points > 0 {
             total_matched_files_count += 1;
         }
 
-        filtered_files_title_bars.push((points, headers.clone()));
+        filtered_files_title_bars.push((matches.points, matches.headers));
 
         filtered_files.push(File {
+            header: file.header.clone(),
             rows: new_file_rows,
             is_main: false,
             name: file.name.clone(),
             last_modified: file.last_modified.clone(),
             id: file.id,
+            sheet_name: file.sheet_name.clone(),
+            sheet_count: file.sheet_count,
+            size: file.size,
+            sort_values: file.sort_values.clone(),
+            cut_top: file.cut_top,
+            cut_bottom: file.cut_bottom,
+            // the rows already start with where they came from
+            origins: vec![],
+            sheet_columns: file.sheet_columns,
+            style: None,
         });
-
-        debug!("iteration duration: {:?}", instant.elapsed());
     }
 
     info!("Searching finished.");
@@ -874,17 +1143,11 @@ fn search_from_files(files: &[File], conditions: &Conditions) -> (Vec<Vec<String
     info!("Adjusting the rows.");
 
     // adjust the rows because they are mispositioned at this point
-    filtered_files.iter_mut().for_each(|file| {
-        let file_header = files
-            .iter()
-            .find(|x| x.id == file.id)
-            .unwrap()
-            .rows
-            .first()
-            .unwrap();
+    filtered_files.par_iter_mut().for_each(|file| {
+        let file_header = header_titles(&file.header);
 
         file.rows.iter_mut().for_each(|cells| {
-            let (intro, cells_clone) = cells.split_at(5);
+            let (intro, cells_clone) = cells.split_at(provenance.len());
 
             let cells_and_headers: HashMap<_, _> = cells_clone
                 .iter()
@@ -905,10 +1168,9 @@ fn search_from_files(files: &[File], conditions: &Conditions) -> (Vec<Vec<String
 
                 // if the header has a field, put that, otherwise insert an empty
                 if let Some(field) = cells_and_headers.get(header) {
-                    new_cells.push(field.to_string());
+                    new_cells.push((*field).clone());
                 } else {
-                    let empty = "".to_string();
-                    new_cells.push(empty);
+                    new_cells.push(Cell::Empty);
                 }
             });
 
@@ -923,73 +1185,239 @@ fn search_from_files(files: &[File], conditions: &Conditions) -> (Vec<Vec<String
         .flat_map(|file| file.rows)
         .collect_vec();
 
+    if sort_by_score {
+        sort_by_score_column(&mut final_rows, provenance);
+    }
+
     headers.0.dedup();
 
-    final_rows.insert(0, headers.0);
+    final_rows.insert(0, headers.0.into_iter().map(Cell::String).collect());
 
     (final_rows, headers.1)
 }
 
-fn sheet_to_rows(sheet: Range<Data>) -> Vec<Vec<String>> {
-    let rows: Vec<Vec<String>> = sheet
-        .rows()
-        .map(|row| {
-            row.iter()
-                .map(|cell| match cell {
-                    Data::String(s) => s.to_owned(),
-                    Data::Float(s) => s.to_string(),
-                    Data::Int(s) => s.to_string(),
-                    Data::DateTime(s) => s.to_string(),
-                    Data::DateTimeIso(s) => s.to_string(),
-                    Data::Empty => "".to_string(),
-                    _ => "unkown".to_owned(),
-                })
-                .collect_vec()
-        })
-        .collect();
+/// sort the found rows by their score, the closest first and the ones without a score last. The
+/// series numbers are the place of the rows in the output, so they're counted again
+fn sort_by_score_column(rows: &mut [Vec<Cell>], provenance: &[ProvenanceColumn]) {
+    let Some(score) = provenance
+        .iter()
+        .position(|column| column.kind == Provenance::Score)
+    else {
+        return;
+    };
+    let score_of = |row: &Vec<Cell>| match row.get(score) {
+        Some(Cell::Float(score)) => *score,
+        _ => -1.0,
+    };
+
+    rows.sort_by(|a, b| score_of(b).total_cmp(&score_of(a)));
+
+    if let Some(series) = provenance
+        .iter()
+        .position(|column| column.kind == Provenance::Series)
+    {
+        for (i, row) in rows.iter_mut().enumerate() {
+            row[series] = (i + 1).into();
+        }
+    }
+}
 
-    rows
+/// read the selected sheets of an upload, a file for each sheet, along with the formulas that
+/// had no cached value. The style of the main file is read too with `styles`, if it's an xlsx one
+fn parse_upload(
+    upload: &Upload,
+    options: &FileOptions,
+    formulas: FormulaMode,
+    sort_cells: &[(u32, u32)],
+    styles: bool,
+) -> Result<(Vec<File>, Vec<MissingValue>)> {
+    let (sheet_count, sheets) =
+        upload.read_sheet_data(&options.sheet, formulas.reads_formulas())?;
+    let mut files = vec![];
+    let mut missing_values = vec![];
+
+    for sheet in sheets {
+        let start = sheet.range.start().unwrap_or_default();
+        let merged_regions = relative_regions(&sheet.merged_regions, start);
+        let sort_values = sort_cells
+            .iter()
+            .map(|cell| {
+                sheet
+                    .range
+                    .get_value(*cell)
+                    .map(Cell::from)
+                    .unwrap_or_default()
+            })
+            .collect();
+        let mut rows = sheet_to_rows(sheet.range);
+        missing_values.extend(apply_formulas(
+            &mut rows,
+            start,
+            &sheet.formulas,
+            formulas,
+            &upload.file_name,
+            &sheet.name,
+        ));
+
+        let header_spec = options.header(&upload.file_name, start.0)?;
+        let (header, rows) = header_spec.split(&upload.file_name, rows, &merged_regions)?;
+        let data_start = start.0 as usize + header_spec.data_start();
+        let origins = (data_start..data_start + rows.len()).collect();
+        let last_column = start.1 + (header.len() as u32).saturating_sub(1);
+        let style = if styles && upload.is_main() && upload.format == Format::Xlsx {
+            let header_row = (data_start - 1) as u32;
+            let style = SheetStyle::read(
+                upload.reader()?,
+                &sheet.name,
+                header_row,
+                (start.1, header.len()),
+            )
+            .with_context(|| format!("error reading the styles of {:?}", upload.file_name))?;
+
+            Some(style)
+        } else {
+            None
+        };
+        let sheet_name = sheet.name;
+
+        let mut file = File::new(
+            upload.file_name.clone(),
+            "unknown".to_string(),
+            rows,
+            upload.is_main(),
+            Uuid::new_v4(),
+            sheet_name,
+            sheet_count,
+        );
+        file.header = header;
+        file.size = upload.size;
+        file.sort_values = sort_values;
+        file.cut_top = options.cut_row as usize;
+        file.cut_bottom = options.cut_bottom as usize;
+        file.origins = origins;
+        file.sheet_columns = (start.1, last_column);
+        file.style = style;
+
+        if let Some(date) = &options.last_mod {
+            file.last_modified = date.clone();
+        }
+
+        files.push(file);
+    }
+
+    Ok((files, missing_values))
 }
 
-fn find_dup_indices(dup: &str, vec: &[impl AsRef<str>]) -> Vec<usize> {
-    let mut indices = vec![];
-    for (i, x) in vec.iter().enumerate() {
-        if x.as_ref() == dup {
-            indices.push(i)
+/// read the selected sheets of an upload for a reply, a file for each sheet
+fn read_reply_upload(
+    upload: &Upload,
+    options: &FileOptions,
+    formulas: FormulaMode,
+) -> Result<Vec<ReplyFile>> {
+    let (_, sheets) = upload.read_sheet_data(&options.sheet, formulas.reads_formulas())?;
+    let parsed = sheets
+        .into_iter()
+        .map(|sheet| process_sheet(&upload.file_name, sheet, formulas))
+        .collect_vec();
+
+    // every selected sheet becomes its own file, so give them distinct names
+    let multi_sheet = parsed.len() > 1;
+    let mut files = vec![];
+
+    for mut file in parsed {
+        if multi_sheet {
+            let stem = file.name.file_stem().unwrap_or_default().to_string_lossy();
+            file.name = format!("{} [{}].{}", stem, file.sheet_name, file.ext).into();
+        }
+
+        // the output is always an xlsx workbook, xls keeps its name like it always did
+        if !matches!(upload.format, Format::Xlsx | Format::Xls) {
+            file.name.set_extension("xlsx");
+        }
+
+        if let Some(date) = &options.last_mod {
+            file.last_modified = date.clone();
         }
+        file.cutting_rows = options.cut_row;
+        file.size = options.size;
+        file.rename = options.rename;
+        file.checked = options.checked;
+        file.reply = options.reply;
+
+        files.push(file);
     }
-    indices
+
+    Ok(files)
 }
 
-fn get_file_extension(filename: &str) -> Option<&str> {
-    filename.rfind('.').map(|index| &filename[index + 1..])
+/// [`parse_uploads`] for a reply, the files come back in the order of the uploads
+async fn read_reply_uploads(
+    uploads: Vec<(Upload, FileOptions)>,
+    formulas: FormulaMode,
+) -> Result<Vec<ReplyFile>> {
+    tokio::task::spawn_blocking(move || {
+        let parsed: Vec<_> = uploads
+            .par_iter()
+            .map(|(upload, options)| read_reply_upload(upload, options, formulas))
+            .collect();
+        let parsed = parsed.into_iter().collect::<Result<Vec<_>>>()?;
+
+        Ok(parsed.into_iter().flatten().collect())
+    })
+    .await
+    .context("error reading the files")?
 }
 
-fn calc_file_row_num_infos(files: &[File]) -> Vec<FileRowNumInfo> {
-    let mut total_rows_count = 0;
-    let file_row_num_infos: Vec<FileRowNumInfo> = files
-        .iter()
-        .enumerate()
-        .map(|(i, file)| {
-            let mut file_row_num_info: FileRowNumInfo = vec![];
-
-            file.rows.iter().enumerate().for_each(|(j, _)| {
-                file_row_num_info.push((
-                    file.last_modified.clone(),
-                    (i + 1).to_string(),
-                    total_rows_count.to_string(),
-                    format!("{}-{}", i + 1, j + 1),
-                    file.name.clone(),
-                ));
+/// parse the uploads in parallel, away from the async runtime. The files come back in the order
+/// of the uploads whichever finishes first, and the temporary files are gone once it's done
+async fn parse_uploads(
+    uploads: Vec<(Upload, FileOptions)>,
+    formulas: FormulaMode,
+    sort_cells: Vec<(u32, u32)>,
+    styles: bool,
+) -> Result<(Vec<File>, Vec<MissingValue>)> {
+    tokio::task::spawn_blocking(move || {
+        let parsed: Vec<_> = uploads
+            .par_iter()
+            .map(|(upload, options)| parse_upload(upload, options, formulas, &sort_cells, styles))
+            .collect();
 
-                total_rows_count += 1;
-            });
+        // the first error in upload order, so the same form always fails the same way
+        let parsed = parsed.into_iter().collect::<Result<Vec<_>>>()?;
+        let (files, missing_values): (Vec<_>, Vec<_>) = parsed.into_iter().unzip();
+
+        Ok((
+            files.into_iter().flatten().collect(),
+            missing_values.into_iter().flatten().collect(),
+        ))
+    })
+    .await
+    .context("error parsing the files")?
+}
 
-            file_row_num_info
-        })
-        .collect();
+fn sheet_to_rows(sheet: Range<Data>) -> Vec<Vec<Cell>> {
+    let rows: Vec<Vec<Cell>> = sheet.rows().map(data_to_cells).collect();
+
+    rows
+}
+
+/// the text of a header row, used to match titles
+fn header_titles(row: &[Cell]) -> Vec<String> {
+    row.iter().map(|cell| cell.to_string()).collect()
+}
 
-    file_row_num_infos
+/// a `true`/`false` form field
+fn parse_flag(name: &str, bytes: &[u8]) -> Result<bool> {
+    let value = String::from_utf8_lossy(bytes);
+
+    value.trim().parse().map_err(|_| Error::InvalidFlag {
+        field: name.to_string(),
+        value: value.trim().to_string(),
+    })
+}
+
+fn get_file_extension(filename: &str) -> Option<&str> {
+    filename.rfind('.').map(|index| &filename[index + 1..])
 }
 
 fn merge_title_bars(title_bars: &[(usize, Vec<String>)]) -> (Vec<String>, Vec<String>) {
@@ -1034,62 +1462,37 @@ fn merge_title_bars(title_bars: &[(usize, Vec<String>)]) -> (Vec<String>, Vec<St
     (main_bar, intersections)
 }
 
-fn process_workbook<R, RS>(
-    workbook: &mut R,
-    // name: &str,
-    other_name: &str,
-    files: &mut ReplyFiles,
-    merged_regions: &Vec<Dimensions>,
-) where
-    R: calamine::Reader<RS>,
-    RS: Read + Seek,
-{
-    if let Ok(range) = workbook.worksheet_range_at(0).unwrap() {
-        let sheet_name = &workbook.worksheets()[0];
-
-        let rows: Vec<Vec<String>> = range
-            .rows()
-            .map(|row| {
-                // trace!("row: {:?}", row);
-                row.iter()
-                    .map(|cell| match cell {
-                        Data::String(s) => s.to_owned(),
-                        Data::Float(s) => s.to_string(),
-                        Data::Int(s) => s.to_string(),
-                        Data::DateTime(s) => s.to_string(),
-                        Data::DateTimeIso(s) => s.to_string(),
-                        Data::Empty => "".to_string(),
-                        _ => "unkown".to_owned(),
-                    })
-                    .collect_vec()
-            })
-            .collect();
-
-        let other_name_clone = other_name.to_owned();
-        let ext = get_file_extension(&other_name_clone).unwrap();
-
-        files.data.push(ReplyFile::new(
-            other_name.to_owned(),
-            "unknown".to_string(),
-            rows,
-            ext.to_string(),
-            0,
-            0,
-            merged_regions.to_owned(),
-            vec![],
-            vec![],
-            false,
-            sheet_name.0.to_string(),
-            false,
-            false,
-        ));
-    }
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_find_dup_indices() {
-        assert_eq!(find_dup_indices("C", &["A", "B", "C", "C"]), vec![2, 3]);
-    }
+fn process_sheet(other_name: &str, sheet: SheetData, formulas: FormulaMode) -> ReplyFile {
+    let start = sheet.range.start().unwrap_or_default();
+    let mut rows = sheet_to_rows(sheet.range);
+    let missing_values = apply_formulas(
+        &mut rows,
+        start,
+        &sheet.formulas,
+        formulas,
+        other_name,
+        &sheet.name,
+    );
+
+    let other_name_clone = other_name.to_owned();
+    let ext = get_file_extension(&other_name_clone).unwrap_or_default();
+
+    let mut file = ReplyFile::new(
+        other_name.to_owned(),
+        "unknown".to_string(),
+        rows,
+        ext.to_string(),
+        0,
+        0,
+        sheet.merged_regions,
+        vec![],
+        vec![],
+        false,
+        sheet.name,
+        false,
+        false,
+    );
+    file.missing_values = missing_values;
+
+    file
 }
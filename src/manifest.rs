@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::header::HeaderSpec;
+use crate::sheet::SheetSelection;
+
+/// the index based fields, still accepted so older clients keep working
+const ARRAY_FIELDS: [&str; 10] = [
+    "last-mod[]",
+    "cut-row[]",
+    "cut-bottom[]",
+    "size[]",
+    "rename[]",
+    "checked[]",
+    "reply[]",
+    "sheet[]",
+    "header-row[]",
+    "header-rows[]",
+];
+
+/// The options of a single uploaded file
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FileOptions {
+    /// the last modified date shown in the output
+    pub last_mod: Option<String>,
+    /// how many rows are cut from the top, a merge cuts them from under the header
+    pub cut_row: u32,
+    /// how many rows a merge cuts from the bottom
+    pub cut_bottom: u32,
+    /// the size of the original file in bytes
+    pub size: u64,
+    pub rename: bool,
+    /// unchecked files are left out of the reply
+    pub checked: bool,
+    pub reply: bool,
+    pub sheet: SheetSelection,
+    /// the zero based row of the sheet the header is on, the first row that isn't empty when it's
+    /// not given
+    pub header_row: Option<u32>,
+    /// how many rows the header spans, they're flattened into one
+    pub header_rows: u32,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        FileOptions {
+            last_mod: None,
+            cut_row: 0,
+            cut_bottom: 0,
+            size: 0,
+            rename: false,
+            checked: true,
+            reply: false,
+            sheet: SheetSelection::default(),
+            header_row: None,
+            header_rows: 1,
+        }
+    }
+}
+
+impl FileOptions {
+    /// where the header is in the rows read from a sheet, they start at `first_row` of the sheet
+    /// since the empty rows above it are never read
+    pub fn header(&self, file: &str, first_row: u32) -> Result<HeaderSpec> {
+        let row = match self.header_row {
+            Some(row) => row.checked_sub(first_row).ok_or_else(|| {
+                invalid(
+                    file,
+                    format!(
+                        "the header row {} is above {}, the first row with values",
+                        row, first_row
+                    ),
+                )
+            })?,
+            None => 0,
+        };
+
+        Ok(HeaderSpec {
+            row: row as usize,
+            rows: self.header_rows as usize,
+        })
+    }
+
+    /// set the option of one of the index based fields from its raw value
+    fn set(&mut self, field: &str, value: &str) -> std::result::Result<(), String> {
+        let value = value.trim();
+
+        match field {
+            "last-mod[]" => {
+                self.last_mod = Some(value.to_string()).filter(|date| !date.is_empty());
+            }
+            "cut-row[]" => self.cut_row = parse_number(field, value)?,
+            "cut-bottom[]" => self.cut_bottom = parse_number(field, value)?,
+            "size[]" => self.size = parse_number(field, value)?,
+            "rename[]" => self.rename = parse_flag(field, value)?,
+            "checked[]" => self.checked = parse_flag(field, value)?,
+            "reply[]" => self.reply = parse_flag(field, value)?,
+            "sheet[]" => self.sheet = SheetSelection::from(value),
+            "header-row[]" => {
+                // an empty value keeps the first row with values
+                self.header_row = Some(value)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| parse_number(field, value))
+                    .transpose()?;
+            }
+            "header-rows[]" => {
+                // an empty value keeps the single header row
+                self.header_rows = parse_number::<u32>(field, value)?.max(1);
+            }
+            _ => unreachable!("{} is not an option field", field),
+        }
+
+        Ok(())
+    }
+}
+
+/// an empty value is 0, like an empty cut row input
+fn parse_number<T: FromStr + Default>(field: &str, value: &str) -> std::result::Result<T, String> {
+    if value.is_empty() {
+        return Ok(T::default());
+    }
+
+    value
+        .parse::<T>()
+        .map_err(|_| format!("{:?} is not a valid {} value", value, field))
+}
+
+/// the reply form sends `true`/`false`, the template sends `Y`/`N`
+fn parse_flag(field: &str, value: &str) -> std::result::Result<bool, String> {
+    match value {
+        "true" | "Y" | "y" => Ok(true),
+        "false" | "N" | "n" | "" => Ok(false),
+        _ => Err(format!("{:?} is not a valid {} value", value, field)),
+    }
+}
+
+/// Collects the per-file options of a form. They either come as a single `manifest` JSON field
+/// keyed by file name, or as the older arrays (`last-mod[]`, `cut-row[]`, ...) matched by index
+#[derive(Debug, Default)]
+pub struct FileOptionsForm {
+    manifest: Option<HashMap<String, serde_json::Value>>,
+    arrays: HashMap<&'static str, Vec<String>>,
+}
+
+impl FileOptionsForm {
+    /// keep the field if it holds file options, returns whether it did
+    pub fn read_field(&mut self, name: &str, bytes: &[u8]) -> Result<bool> {
+        if name == "manifest" {
+            let manifest =
+                serde_json::from_slice(bytes).map_err(|e| invalid("manifest", e.to_string()))?;
+            self.manifest = Some(manifest);
+
+            return Ok(true);
+        }
+
+        let Some(field) = ARRAY_FIELDS.iter().find(|field| **field == name) else {
+            return Ok(false);
+        };
+
+        let value =
+            String::from_utf8(bytes.to_vec()).with_context(|| format!("error parsing {}", name))?;
+        self.arrays.entry(field).or_default().push(value);
+
+        Ok(true)
+    }
+
+    /// match the options to the uploaded files, in the same order as `file_names`
+    pub fn resolve(self, file_names: &[&str]) -> Result<Vec<FileOptions>> {
+        match self.manifest {
+            Some(manifest) => {
+                if !self.arrays.is_empty() {
+                    debug!("Got a manifest, ignoring {:?}", self.arrays.keys());
+                }
+
+                resolve_manifest(manifest, file_names)
+            }
+            None => resolve_arrays(self.arrays, file_names),
+        }
+    }
+}
+
+fn invalid(file: &str, reason: impl Into<String>) -> Error {
+    Error::InvalidOptions {
+        file: file.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn resolve_manifest(
+    mut manifest: HashMap<String, serde_json::Value>,
+    file_names: &[&str],
+) -> Result<Vec<FileOptions>> {
+    let mut options = vec![];
+
+    for file_name in file_names {
+        if file_names.iter().filter(|name| *name == file_name).count() > 1 {
+            return Err(invalid(
+                file_name,
+                "more than one file has this name, so the manifest can't tell them apart",
+            ));
+        }
+
+        let value = manifest
+            .remove(*file_name)
+            .ok_or_else(|| invalid(file_name, "missing from the manifest"))?;
+        let file_options = serde_json::from_value::<FileOptions>(value)
+            .map_err(|e| invalid(file_name, e.to_string()))?;
+
+        options.push(file_options);
+    }
+
+    if let Some(file_name) = manifest.keys().next() {
+        return Err(invalid(
+            file_name,
+            "in the manifest but no such file was uploaded",
+        ));
+    }
+
+    Ok(options)
+}
+
+fn resolve_arrays(
+    arrays: HashMap<&'static str, Vec<String>>,
+    file_names: &[&str],
+) -> Result<Vec<FileOptions>> {
+    let mut options = vec![FileOptions::default(); file_names.len()];
+
+    // go through the fields in a fixed order so the same form always gives the same error
+    for field in ARRAY_FIELDS {
+        let Some(values) = arrays.get(field) else {
+            continue;
+        };
+
+        if values.len() > file_names.len() {
+            return Err(Error::OptionCountMismatch {
+                field: field.to_string(),
+                values: values.len(),
+                files: file_names.len(),
+            });
+        }
+
+        for (i, file_name) in file_names.iter().enumerate() {
+            let value = values
+                .get(i)
+                .ok_or_else(|| invalid(file_name, format!("no {} value was sent", field)))?;
+
+            options[i]
+                .set(field, value)
+                .map_err(|reason| invalid(file_name, reason))?;
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::Cell;
+
+    #[test]
+    fn test_header_row() {
+        // the sheet has two empty rows on top, so the rows read from it start at its third one
+        let rows: Vec<Vec<Cell>> = vec![
+            vec!["Report".into()],
+            vec!["Name".into(), "Qty".into()],
+            vec!["Ali".into(), Cell::Int(3)],
+        ];
+
+        let mut form = FileOptionsForm::default();
+        form.read_field("manifest", br#"{"a.xlsx": {"header-row": 3}}"#)
+            .unwrap();
+        let options = form.resolve(&["a.xlsx"]).unwrap();
+        let spec = options[0].header("a.xlsx", 2).unwrap();
+        let (header, data) = spec.split("a.xlsx", rows.clone(), &[]).unwrap();
+        assert_eq!(header, vec!["Name".into(), "Qty".into()]);
+        assert_eq!(data, vec![vec!["Ali".into(), Cell::Int(3)]]);
+
+        // without a header row it's the first row with values
+        let spec = FileOptions::default().header("a.xlsx", 2).unwrap();
+        let (header, _) = spec.split("a.xlsx", rows, &[]).unwrap();
+        assert_eq!(header, vec!["Report".into(), Cell::Empty]);
+
+        let options = FileOptions {
+            header_row: Some(1),
+            ..FileOptions::default()
+        };
+        let error = options.header("a.xlsx", 2).unwrap_err();
+        assert!(matches!(error, Error::InvalidOptions { .. }));
+    }
+
+    #[test]
+    fn test_resolve_options() {
+        let mut form = FileOptionsForm::default();
+        form.read_field("cut-row[]", b"2").unwrap();
+        form.read_field("cut-row[]", b"").unwrap();
+        form.read_field("checked[]", b"Y").unwrap();
+        form.read_field("checked[]", b"false").unwrap();
+        form.read_field("size[]", b"5000000000").unwrap();
+        form.read_field("size[]", b"").unwrap();
+
+        let options = form.resolve(&["a.xlsx", "b.xlsx"]).unwrap();
+        assert_eq!(options[0].size, 5_000_000_000);
+        assert_eq!(options[0].cut_row, 2);
+        assert_eq!(options[1].cut_row, 0);
+        assert!(options[0].checked);
+        assert!(!options[1].checked);
+
+        let mut form = FileOptionsForm::default();
+        form.read_field("size[]", b"10").unwrap();
+        let error = form.resolve(&["a.xlsx", "b.xlsx"]).unwrap_err();
+        assert!(error.to_string().contains("b.xlsx"));
+
+        let mut form = FileOptionsForm::default();
+        form.read_field("manifest", br#"{"b.xlsx": {"cut-row": 3}}"#)
+            .unwrap();
+        let error = form.resolve(&["a.xlsx", "b.xlsx"]).unwrap_err();
+        assert!(error.to_string().contains("a.xlsx"));
+
+        let mut form = FileOptionsForm::default();
+        form.read_field(
+            "manifest",
+            br#"{"a.xlsx": {"sheet": 2}, "b.xlsx": {"sheet": "2"}}"#,
+        )
+        .unwrap();
+        let options = form.resolve(&["a.xlsx", "b.xlsx"]).unwrap();
+        assert_eq!(options[0].sheet, SheetSelection::Index(2));
+        assert_eq!(options[1].sheet, SheetSelection::Name("2".to_string()));
+
+        let mut form = FileOptionsForm::default();
+        let error = form.read_field("manifest", b"{\"a.xlsx\":").unwrap_err();
+        assert!(matches!(error, Error::InvalidOptions { .. }));
+    }
+}
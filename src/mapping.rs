@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::Cell;
+use crate::error::{Error, Result};
+use crate::query::Query;
+use crate::sheet::SheetSelection;
+use crate::upload::Upload;
+use crate::File;
+
+/// Renames the header variants different files use to a single canonical name, so "Qty",
+/// "Quantity" and "الكمية" can all become "Quantity" before the columns are matched up
+#[derive(Clone, Debug, Default)]
+pub struct ColumnMapping {
+    /// canonical names keyed by every variant of them (the canonical name included)
+    aliases: HashMap<String, String>,
+}
+
+/// A header that isn't in the mapping, for the report sheet
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnmappedHeader {
+    pub file: String,
+    pub column: String,
+}
+
+impl ColumnMapping {
+    /// a JSON object of canonical names and their variants, `{"Quantity": ["Qty", "الكمية"]}`
+    pub fn from_json(bytes: &[u8]) -> Result<Self> {
+        let json: HashMap<String, serde_json::Value> =
+            serde_json::from_slice(bytes).map_err(|e| invalid(e.to_string()))?;
+        let mut mapping = ColumnMapping::default();
+
+        for (canonical, variants) in json {
+            let variants = match variants {
+                serde_json::Value::String(variant) => vec![variant],
+                serde_json::Value::Array(variants) => variants
+                    .into_iter()
+                    .map(|variant| match variant {
+                        serde_json::Value::String(variant) => Ok(variant),
+                        variant => Err(invalid(format!(
+                            "the variants of {:?} have to be text, got {}",
+                            canonical, variant
+                        ))),
+                    })
+                    .collect::<Result<_>>()?,
+                variants => {
+                    return Err(invalid(format!(
+                        "the variants of {:?} have to be a list, got {}",
+                        canonical, variants
+                    )))
+                }
+            };
+
+            mapping.insert(&canonical, &variants)?;
+        }
+
+        Ok(mapping)
+    }
+
+    /// an uploaded mapping, either a `.json` file or the first sheet of a workbook (or CSV) with
+    /// a canonical name followed by its variants on every row
+    pub fn from_upload(upload: &Upload) -> Result<Self> {
+        let bytes = upload.bytes()?;
+        let is_json = upload.file_name.to_ascii_lowercase().ends_with(".json")
+            || bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{');
+
+        if is_json {
+            return Self::from_json(&bytes);
+        }
+
+        let (_, sheets) = upload.read_sheets(&SheetSelection::First)?;
+        let mut mapping = ColumnMapping::default();
+
+        for (_, range) in sheets {
+            for row in range.rows() {
+                let names = row
+                    .iter()
+                    .map(|name| name.to_string().trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<_>>();
+
+                if let Some((canonical, variants)) = names.split_first() {
+                    mapping.insert(canonical, variants)?;
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// a variant can only stand for one canonical name
+    fn insert(&mut self, canonical: &str, variants: &[String]) -> Result<()> {
+        let canonical = canonical.trim();
+
+        for variant in std::iter::once(canonical).chain(variants.iter().map(String::as_str)) {
+            let previous = self
+                .aliases
+                .insert(header_key(variant), canonical.to_string())
+                .filter(|previous| previous != canonical);
+
+            if let Some(previous) = previous {
+                return Err(invalid(format!(
+                    "{:?} is mapped to both {:?} and {:?}",
+                    variant.trim(),
+                    previous,
+                    canonical
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the canonical name of a header, if it's in the mapping
+    pub fn canonical(&self, header: &str) -> Option<&str> {
+        self.aliases.get(&header_key(header)).map(String::as_str)
+    }
+
+    /// rename the headers of the files to their canonical names. Returns the headers that aren't
+    /// in the mapping, they're kept as they are
+    pub fn apply(&self, files: &mut [File]) -> Vec<UnmappedHeader> {
+        let mut unmapped = vec![];
+
+        for file in files {
+            let file_name = file.display_name().replace("-MAIN", "");
+
+            for cell in file.header.iter_mut() {
+                let header = cell.as_text().trim().to_string();
+                if header.is_empty() {
+                    continue;
+                }
+
+                match self.canonical(&header) {
+                    Some(canonical) => *cell = Cell::String(canonical.to_string()),
+                    None => {
+                        let header = UnmappedHeader {
+                            file: file_name.clone(),
+                            column: header,
+                        };
+
+                        if !unmapped.contains(&header) {
+                            unmapped.push(header);
+                        }
+                    }
+                }
+            }
+        }
+
+        unmapped
+    }
+
+    /// the columns of a search query go by the canonical names too
+    pub fn apply_to_query(&self, query: &mut Query) {
+        for term in query.terms_mut() {
+            if let Some(column) = &mut term.column {
+                if let Some(canonical) = self.canonical(column) {
+                    *column = canonical.to_string();
+                }
+            }
+        }
+    }
+}
+
+/// headers are compared without the line breaks of wrapped headers, surrounding spaces or case
+pub fn header_key(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| *c != '\n' && *c != '\r')
+        .collect::<String>()
+        .trim()
+        .to_lowercase()
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidMapping {
+        reason: reason.into(),
+    }
+}
+
+/// list the headers that aren't in the mapping on a sheet of their own
+pub fn write_unmapped_headers(workbook: &mut Workbook, unmapped: &[UnmappedHeader]) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Unmapped Headers")
+        .context("error setting name of unmapped headers sheet")?;
+
+    for (j, header) in ["File Name", "Column"].iter().enumerate() {
+        worksheet
+            .write_string(0, j as u16, *header)
+            .context("error writing header")?;
+    }
+
+    for (i, header) in unmapped.iter().enumerate() {
+        let row = (i + 1) as u32;
+
+        for (j, text) in [&header.file, &header.column].iter().enumerate() {
+            worksheet
+                .write_string(row, j as u16, text.as_str())
+                .context("error writing unmapped header")?;
+        }
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_column_mapping() {
+        let mapping = ColumnMapping::from_json(
+            r#"{"Quantity": ["Qty", "الكمية"], "City": "Town"}"#.as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(mapping.canonical("qty"), Some("Quantity"));
+        assert_eq!(mapping.canonical(" الكمية\n"), Some("Quantity"));
+        assert_eq!(mapping.canonical("Quantity"), Some("Quantity"));
+        assert_eq!(mapping.canonical("TOWN"), Some("City"));
+        assert_eq!(mapping.canonical("Name"), None);
+
+        assert!(ColumnMapping::from_json(br#"{"Quantity": ["Qty"], "Amount": ["qty"]}"#).is_err());
+        assert!(ColumnMapping::from_json(br#"{"Quantity": 1}"#).is_err());
+    }
+}
@@ -1,9 +1,32 @@
+use crate::align::{write_column_report, ColumnDifference};
+use crate::cell::{Cell, CellFormats, DateFormat};
+use crate::dedup::{write_duplicates, DuplicateRow};
 use crate::error::Result;
+use crate::formula::{write_missing_values, MissingValue};
+use crate::mapping::{write_unmapped_headers, UnmappedHeader};
+use crate::provenance::ProvenanceColumn;
+use crate::style::SheetStyle;
+use crate::summary::{write_summary, FileSummary};
 use anyhow::Context;
 use rust_xlsxwriter::Workbook;
 
 pub struct MergeFiles {
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Vec<Cell>>,
+    pub date_format: DateFormat,
+    /// formulas without a cached value, only looked for when asked to
+    pub missing_values: Vec<MissingValue>,
+    /// the columns each file lacked or added, only when the columns are aligned by header
+    pub column_differences: Vec<ColumnDifference>,
+    /// the headers that weren't in the column mapping, only when one was uploaded
+    pub unmapped_headers: Vec<UnmappedHeader>,
+    /// the rows dropped for repeating the key of another row
+    pub duplicates: Vec<DuplicateRow>,
+    /// the columns every merged row starts with, before the columns of its file
+    pub provenance: Vec<ProvenanceColumn>,
+    /// the header styling, column number formats and widths of the main file, when asked for
+    pub style: Option<SheetStyle>,
+    /// how many rows each file had and what happened to them, only when asked for
+    pub summaries: Vec<FileSummary>,
 }
 
 // TODO: write a trait instead for both search and merge
@@ -12,14 +35,57 @@ impl MergeFiles {
     pub fn write_to_buffer(&mut self) -> Result<Vec<u8>> {
         let mut workbook = Workbook::new();
         let worksheet = workbook.add_worksheet();
+        let formats = CellFormats::new(self.date_format.clone());
 
         // write manually to the worksheet
+        let offset = self.provenance.len();
         for (i, row) in self.rows.iter().enumerate() {
             for (j, cell) in row.iter().enumerate() {
-                worksheet.write_string(i as u32, j as u16, cell).context("error writing to the new worksheet")?;
+                // the provenance columns aren't the main file's, they're never styled
+                let format = j
+                    .checked_sub(offset)
+                    .and_then(|column| self.style.as_ref()?.format(i, column));
+
+                match format {
+                    Some(format) => {
+                        cell.write_with_format(worksheet, i as u32, j as u16, &formats, format)
+                    }
+                    None => cell.write(worksheet, i as u32, j as u16, &formats),
+                }
+                .context("error writing to the new worksheet")?;
             }
         }
 
+        if let Some(style) = &self.style {
+            style.set_widths(worksheet, offset as u16)?;
+        }
+
+        if !self.summaries.is_empty() {
+            write_summary(&mut workbook, &self.summaries)?;
+        }
+
+        if !self.missing_values.is_empty() {
+            write_missing_values(&mut workbook, &self.missing_values)?;
+        }
+
+        if !self.column_differences.is_empty() {
+            write_column_report(&mut workbook, &self.column_differences)?;
+        }
+
+        if !self.unmapped_headers.is_empty() {
+            write_unmapped_headers(&mut workbook, &self.unmapped_headers)?;
+        }
+
+        if !self.duplicates.is_empty() {
+            let header = self
+                .rows
+                .first()
+                .and_then(|header| header.get(self.provenance.len()..))
+                .unwrap_or_default();
+
+            write_duplicates(&mut workbook, header, &self.duplicates, &formats)?;
+        }
+
         let buf = workbook
             .save_to_buffer()
             .context("Failed to save workbook to buffer")?
@@ -28,7 +94,7 @@ impl MergeFiles {
         Ok(buf)
     }
 
-    pub fn write_to_vec(&self) -> Vec<Vec<String>> {
+    pub fn write_to_vec(&self) -> Vec<Vec<Cell>> {
         self.rows.clone()
     }
 }
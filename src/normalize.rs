@@ -0,0 +1,192 @@
+use std::ops::Range;
+
+use serde::Deserialize;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{Error, Result};
+
+/// A way text is evened out before it's searched
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizeMode {
+    /// unicode compatibility forms, e.g. full width letters and ligatures
+    Nfkc,
+    /// lower case, `Cairo` is `cairo`
+    Case,
+    /// accents and harakat dropped, `é` is `e`
+    Diacritics,
+    /// the forms of alef, hamza, taa marbuta and alef maqsura folded and tatweel dropped
+    Arabic,
+    /// every kind of space is a single space, none at the ends
+    Whitespace,
+}
+
+/// The normalizations a search is done with, none of them by default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Normalization {
+    pub nfkc: bool,
+    pub case: bool,
+    pub diacritics: bool,
+    pub arabic: bool,
+    pub whitespace: bool,
+}
+
+/// A normalized text along with where each of its bytes came from in the original
+#[derive(Clone, Debug, PartialEq)]
+pub struct Normalized {
+    pub text: String,
+    /// the bytes of the original text every byte of `text` came from
+    origins: Vec<Range<usize>>,
+}
+
+impl Normalized {
+    /// the part of the original text a span of the normalized one came from
+    pub fn original(&self, span: &Range<usize>) -> Range<usize> {
+        if span.is_empty() {
+            return 0..0;
+        }
+
+        self.origins[span.start].start..self.origins[span.end - 1].end
+    }
+
+    fn push(&mut self, c: char, origin: Range<usize>) {
+        self.text.push(c);
+        for _ in 0..c.len_utf8() {
+            self.origins.push(origin.clone());
+        }
+    }
+}
+
+impl Normalization {
+    /// a JSON list of the modes, `["case", "arabic"]`
+    pub fn parse_list(bytes: &[u8]) -> Result<Self> {
+        let modes: Vec<NormalizeMode> =
+            serde_json::from_slice(bytes).map_err(|e| Error::InvalidNormalization {
+                reason: format!(
+                    "{} (the modes are nfkc, case, diacritics, arabic and whitespace)",
+                    e
+                ),
+            })?;
+
+        Ok(Normalization {
+            nfkc: modes.contains(&NormalizeMode::Nfkc),
+            case: modes.contains(&NormalizeMode::Case),
+            diacritics: modes.contains(&NormalizeMode::Diacritics),
+            arabic: modes.contains(&NormalizeMode::Arabic),
+            whitespace: modes.contains(&NormalizeMode::Whitespace),
+        })
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Normalization::default()
+    }
+
+    /// normalize a text. A letter and the marks after it are normalized together, so whatever
+    /// comes out of them maps back to all of them
+    pub fn apply(&self, text: &str) -> Normalized {
+        let mut normalized = Normalized {
+            text: String::with_capacity(text.len()),
+            origins: Vec::with_capacity(text.len()),
+        };
+        if self.is_none() {
+            normalized.text.push_str(text);
+            normalized.origins.extend((0..text.len()).map(|i| i..i + 1));
+            return normalized;
+        }
+
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let mut end = start + c.len_utf8();
+            while let Some((i, mark)) = chars.next_if(|(_, c)| is_combining_mark(*c)) {
+                end = i + mark.len_utf8();
+            }
+
+            for c in self.cluster(&text[start..end]) {
+                if self.whitespace && c.is_whitespace() {
+                    // a space only goes in between two other things, see the end below
+                    if normalized.text.is_empty() || normalized.text.ends_with(' ') {
+                        continue;
+                    }
+                    normalized.push(' ', start..end);
+                } else {
+                    normalized.push(c, start..end);
+                }
+            }
+        }
+
+        if self.whitespace && normalized.text.ends_with(' ') {
+            normalized.text.pop();
+            normalized.origins.pop();
+        }
+
+        normalized
+    }
+
+    /// the characters a letter and its marks come out as
+    fn cluster(&self, cluster: &str) -> Vec<char> {
+        let mut chars: Vec<char> = if self.nfkc {
+            cluster.nfkc().collect()
+        } else {
+            cluster.chars().collect()
+        };
+
+        if self.diacritics {
+            chars = chars
+                .into_iter()
+                .collect::<String>()
+                .nfd()
+                .filter(|c| !is_combining_mark(*c))
+                .nfc()
+                .collect();
+        }
+
+        if self.arabic {
+            chars = chars.into_iter().filter_map(fold_arabic).collect();
+        }
+
+        if self.case {
+            chars = chars.into_iter().flat_map(char::to_lowercase).collect();
+        }
+
+        chars
+    }
+}
+
+/// the letter an arabic one is searched as, tatweel is only there to stretch a word
+fn fold_arabic(c: char) -> Option<char> {
+    match c {
+        '\u{0640}' => None,
+        'أ' | 'إ' | 'آ' | 'ٱ' => Some('ا'),
+        'ة' => Some('ه'),
+        'ى' | 'ئ' => Some('ي'),
+        'ؤ' => Some('و'),
+        c => Some(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalization =
+            Normalization::parse_list(br#"["nfkc", "case", "diacritics", "arabic", "whitespace"]"#)
+                .unwrap();
+        assert!(Normalization::parse_list(br#"["accents"]"#).is_err());
+
+        let normalized = normalization.apply("\u{a0} Café\u{a0}\u{a0}CAIRO ");
+        assert_eq!(normalized.text, "cafe cairo");
+        // `e` came from `é` and the space from the first of the non breaking spaces
+        assert_eq!(normalized.original(&(3..5)), 6..10);
+
+        assert_eq!(normalization.apply("إسْكَنْدَرِيَّة").text, "اسكندريه");
+        assert_eq!(normalization.apply("مـدرسـة").text, "مدرسه");
+        assert_eq!(normalization.apply("ﬁle").text, "file");
+
+        let none = Normalization::default().apply("Café");
+        assert_eq!(none.text, "Café");
+        assert_eq!(none.original(&(3..5)), 3..5);
+    }
+}
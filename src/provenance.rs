@@ -0,0 +1,231 @@
+use serde::Deserialize;
+use size::Size;
+
+use crate::cell::Cell;
+use crate::error::{Error, Result};
+use crate::formula::cell_name;
+use crate::query::Score;
+use crate::File;
+
+/// A column telling where a merged or found row came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provenance {
+    /// the modified date sent for the file
+    Date,
+    /// the number of the file the row is from
+    Files,
+    /// the number of the row in the output
+    Series,
+    /// the number of the file and of the row in it, e.g. `2-5`
+    Count,
+    /// the name of the file, with the sheet when the workbook has more than one
+    File,
+    /// the sheet the row is from
+    Sheet,
+    /// the (one based) row number the row had in its sheet
+    Row,
+    /// the cells the row spanned in its sheet, e.g. `A7:F7`
+    Range,
+    /// the size of the uploaded file
+    Size,
+    /// how close the fuzzy terms of a search came to the row, only a search has it
+    Score,
+    /// the text the fuzzy terms of a search matched in the row
+    Variant,
+}
+
+impl Provenance {
+    fn default_name(&self) -> &'static str {
+        match self {
+            Provenance::Date => "Date Modified",
+            Provenance::Files => "Number of Files",
+            Provenance::Series => "Series Number",
+            Provenance::Count => "Count Number",
+            Provenance::File => "File Name",
+            Provenance::Sheet => "Sheet Name",
+            Provenance::Row => "Row Number",
+            Provenance::Range => "Cell Range",
+            Provenance::Size => "File Size",
+            Provenance::Score => "Match Score",
+            Provenance::Variant => "Matched Variant",
+        }
+    }
+}
+
+/// A provenance column along with the header it's written under
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProvenanceColumn {
+    pub kind: Provenance,
+    pub name: String,
+}
+
+/// how a column is sent, its kind on its own or along with a name of its own
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColumnSpec {
+    Kind(Provenance),
+    Named {
+        column: Provenance,
+        name: Option<String>,
+    },
+}
+
+impl ProvenanceColumn {
+    pub fn new(kind: Provenance) -> Self {
+        ProvenanceColumn {
+            kind,
+            name: kind.default_name().to_string(),
+        }
+    }
+
+    /// the five columns every output used to start with
+    pub fn defaults() -> Vec<Self> {
+        [
+            Provenance::Date,
+            Provenance::Files,
+            Provenance::Series,
+            Provenance::Count,
+            Provenance::File,
+        ]
+        .into_iter()
+        .map(ProvenanceColumn::new)
+        .collect()
+    }
+
+    /// a JSON list of columns in the order they're written in, either a kind or a kind with a
+    /// header of its own, `["file", {"column": "row", "name": "Line"}]`. An empty list leaves
+    /// them all out
+    pub fn parse_list(bytes: &[u8]) -> Result<Vec<Self>> {
+        let specs: Vec<ColumnSpec> =
+            serde_json::from_slice(bytes).map_err(|e| Error::InvalidProvenance {
+                reason: format!(
+                    "{} (the columns are date, files, series, count, file, sheet, row, range, size, score and variant)",
+                    e
+                ),
+            })?;
+
+        let columns = specs
+            .into_iter()
+            .map(|spec| match spec {
+                ColumnSpec::Kind(kind) => ProvenanceColumn::new(kind),
+                ColumnSpec::Named { column, name } => ProvenanceColumn {
+                    kind: column,
+                    name: name.unwrap_or_else(|| column.default_name().to_string()),
+                },
+            })
+            .collect();
+
+        Ok(columns)
+    }
+
+    pub fn value(&self, source: &RowSource) -> Cell {
+        let file = source.file;
+
+        match self.kind {
+            Provenance::Date => file.last_modified.as_str().into(),
+            Provenance::Files => source.file_number.into(),
+            Provenance::Series => source.series.into(),
+            Provenance::Count => source.count.as_str().into(),
+            Provenance::File => file.display_name().replace("-MAIN", "").into(),
+            Provenance::Sheet => file.sheet_name.as_str().into(),
+            Provenance::Row => match file.origins.get(source.index) {
+                Some(row) => (row + 1).into(),
+                None => Cell::Empty,
+            },
+            Provenance::Range => match file.origins.get(source.index) {
+                Some(row) => {
+                    let row = *row as i64 + 1;
+                    let (first, last) = file.sheet_columns;
+
+                    format!(
+                        "{}:{}",
+                        cell_name(row, first as i64 + 1),
+                        cell_name(row, last as i64 + 1)
+                    )
+                    .into()
+                }
+                None => Cell::Empty,
+            },
+            Provenance::Size => Size::from_bytes(file.size).to_string().into(),
+            Provenance::Score => match &source.score {
+                Some(score) => Cell::Float(score.score),
+                None => Cell::Empty,
+            },
+            Provenance::Variant => match &source.score {
+                Some(score) => score.variant.as_str().into(),
+                None => Cell::Empty,
+            },
+        }
+    }
+}
+
+/// Where a row came from, the numbers are counted by whoever writes the rows
+pub struct RowSource<'a> {
+    pub file: &'a File,
+    /// one based
+    pub file_number: usize,
+    /// one based
+    pub series: usize,
+    pub count: String,
+    /// the index of the row in the rows of its file
+    pub index: usize,
+    /// the score of a fuzzy search and the text it matched
+    pub score: Option<Score>,
+}
+
+/// the provenance cells of a row, in the order of the columns
+pub fn provenance_cells(columns: &[ProvenanceColumn], source: &RowSource) -> Vec<Cell> {
+    columns.iter().map(|column| column.value(source)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file;
+
+    #[test]
+    fn test_rename_columns() {
+        let columns =
+            ProvenanceColumn::parse_list(br#"["file", {"column": "range", "name": "Cells"}]"#)
+                .unwrap();
+        assert_eq!(columns[0], ProvenanceColumn::new(Provenance::File));
+        assert_eq!(columns[0].name, "File Name");
+        assert_eq!(columns[1].kind, Provenance::Range);
+        assert_eq!(columns[1].name, "Cells");
+    }
+
+    #[test]
+    fn test_unknown_column() {
+        assert!(matches!(
+            ProvenanceColumn::parse_list(br#"["origin"]"#),
+            Err(Error::InvalidProvenance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_provenance_cells() {
+        let columns = [
+            ProvenanceColumn::new(Provenance::File),
+            ProvenanceColumn::new(Provenance::Range),
+        ];
+        let mut file = test_file("a.xlsx", &[], &[&[], &[]]);
+        file.sheet_name = "Data".to_string();
+        file.sheet_count = 2;
+        file.origins = vec![4, 6];
+        file.sheet_columns = (1, 3);
+        let source = RowSource {
+            file: &file,
+            file_number: 1,
+            series: 2,
+            count: "1-2".to_string(),
+            index: 1,
+            score: None,
+        };
+
+        assert_eq!(
+            provenance_cells(&columns, &source),
+            vec![Cell::from("a.xlsx [Data]"), Cell::from("B7:D7")]
+        );
+    }
+}
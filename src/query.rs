@@ -0,0 +1,952 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::Chars;
+
+use chrono::NaiveDateTime;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use strsim::{levenshtein, normalized_levenshtein};
+
+use crate::cell::{parse_iso_date, Cell};
+use crate::error::{Error, Result};
+use crate::mapping::header_key;
+use crate::normalize::Normalization;
+use crate::search::Search;
+
+/// How the text of a term is looked for in a cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    Contains,
+    /// the cell has to be the text, not just contain it
+    Exact,
+    StartsWith,
+    EndsWith,
+    /// the text is a regular expression the cell has a match of
+    Regex,
+    /// the cell, or some words of it, are close to the text, see [`Fuzziness`]
+    Fuzzy,
+    /// the value of the cell is more than the number or the date of the text
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    /// the value of the cell is in a range like `1..10`, the ends included
+    Between,
+    /// the cell has nothing but spaces, the text doesn't matter
+    IsEmpty,
+    IsNotEmpty,
+}
+
+impl MatchMode {
+    /// whether the text of a cell is looked at, the others look at its value or whether it has one
+    pub fn is_text(self) -> bool {
+        matches!(
+            self,
+            MatchMode::Contains
+                | MatchMode::Exact
+                | MatchMode::StartsWith
+                | MatchMode::EndsWith
+                | MatchMode::Regex
+                | MatchMode::Fuzzy
+        )
+    }
+}
+
+/// A number or a date a cell is compared with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Date(NaiveDateTime),
+}
+
+impl Value {
+    /// a number, or an ISO date with or without a time
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+
+        match text.parse::<f64>() {
+            Ok(number) if number.is_finite() => Some(Value::Number(number)),
+            _ => parse_iso_date(text).map(Value::Date),
+        }
+    }
+
+    /// the value of a typed cell, text that is a number or a date counts too
+    pub fn of_cell(cell: &Cell) -> Option<Self> {
+        match cell {
+            Cell::Int(i) => Some(Value::Number(*i as f64)),
+            Cell::Float(f) => Some(Value::Number(*f)),
+            Cell::DateTime(date) => Some(Value::Date(*date)),
+            Cell::String(text) | Cell::DateTimeIso(text) => Value::parse(text),
+            Cell::Formula { value, .. } => Value::of_cell(value),
+            _ => None,
+        }
+    }
+
+    /// numbers only compare with numbers and dates with dates
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// How far a fuzzy term can be from what it matches, two edits when neither is given
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fuzziness {
+    /// the most characters added, removed or changed
+    pub distance: Option<usize>,
+    /// the least similarity, from 0 to 1, one less the edits over the length of the longer text
+    pub similarity: Option<f64>,
+}
+
+/// How close a fuzzy search came to a row along with the text it matched, for the output
+#[derive(Clone, Debug, PartialEq)]
+pub struct Score {
+    pub score: f64,
+    pub variant: String,
+}
+
+/// Where the fuzzy terms of a query came closest to a row
+#[derive(Clone, Debug, PartialEq)]
+pub struct FuzzyMatch {
+    /// the lowest similarity of the terms
+    pub score: f64,
+    /// the cell each term matched along with the part of it that matched, in bytes
+    pub variants: Vec<(usize, Range<usize>)>,
+}
+
+/// A single thing a cell of a row is checked for
+#[derive(Clone, Debug)]
+pub struct Term {
+    /// only the cells under this header are checked, any cell of the row otherwise
+    pub column: Option<String>,
+    pub text: String,
+    pub mode: MatchMode,
+    /// only used by fuzzy terms
+    pub fuzziness: Fuzziness,
+    /// what the cells are compared with, one value or the two ends of a range
+    values: Vec<Value>,
+    /// the compiled text of a regex term
+    regex: Option<Regex>,
+}
+
+/// A search query, parsed from text like `Status:"Closed" AND (City:Cairo OR City:/^Giza/)`
+#[derive(Clone, Debug)]
+pub enum Query {
+    Term(Term),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    /// parse a query. Terms are words or quoted text, `Column:text` checks a single column and
+    /// `Column=text` wants the whole cell. A bare `text*` is what a cell starts with, `*text` what
+    /// it ends with, `text~` what it's close to and `/text/` a regular expression. `Column>10`,
+    /// `>=`, `<` and `<=` compare numbers and dates, `Column:1..10` is a range of them and
+    /// `Column=""` an empty cell. `AND`, `OR` and `NOT` are written in capitals, terms next to
+    /// each other are all needed, and `AND` binds tighter than `OR`
+    pub fn parse(query: &str) -> Result<Self> {
+        let tokens = tokenize(query)?;
+        if tokens.is_empty() {
+            return Err(invalid("the query is empty"));
+        }
+
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let query = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(query),
+            Some(Token::Close) => Err(invalid("there's a `)` without a `(`")),
+            Some(token) => Err(invalid(format!("didn't expect {} here", token))),
+        }
+    }
+
+    /// the query the JSON conditions always meant, a row matches any of the conditions when one
+    /// of its cells contains the data and every intersection is a cell of it, unless a condition
+    /// has a mode of its own
+    pub fn from_conditions(conditions: &[Search]) -> Result<Self> {
+        let term = |search: &Search, mode: MatchMode| {
+            let column = search.title.clone().filter(|title| !title.is_empty());
+            let mut term = Term::new(column, search.data.clone(), search.mode.unwrap_or(mode))?;
+            term.fuzziness = Fuzziness::new(search.distance, search.similarity)?;
+
+            Ok(Query::Term(term))
+        };
+
+        let queries = conditions
+            .iter()
+            .map(|search| {
+                let data = term(search, MatchMode::Contains)?;
+                let intersections = search
+                    .intersections
+                    .iter()
+                    .map(|search| term(search, MatchMode::Exact));
+
+                std::iter::once(Ok(data))
+                    .chain(intersections)
+                    .collect::<Result<Vec<_>>>()
+                    .map(Query::And)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Query::Or(queries))
+    }
+
+    /// whether a row matches, `text` being the text of its cells, `cells` the typed ones and
+    /// `headers` the keys of its header (see [`header_key`])
+    pub fn matches(&self, text: &[impl AsRef<str>], cells: &[Cell], headers: &[String]) -> bool {
+        match self {
+            Query::Term(term) => term.matches(text, cells, headers),
+            Query::And(queries) => queries
+                .iter()
+                .all(|query| query.matches(text, cells, headers)),
+            Query::Or(queries) => queries
+                .iter()
+                .any(|query| query.matches(text, cells, headers)),
+            Query::Not(query) => !query.matches(text, cells, headers),
+        }
+    }
+
+    /// how close the fuzzy terms came to a matched row, nothing when none of them found anything
+    /// in it. Like [`Query::matches`] the cells are text and the headers are keys
+    pub fn fuzzy_match(&self, cells: &[impl AsRef<str>], headers: &[String]) -> Option<FuzzyMatch> {
+        let closest = self
+            .highlights()
+            .into_iter()
+            .filter(|term| term.mode == MatchMode::Fuzzy)
+            .filter_map(|term| term.closest_in_row(cells, headers))
+            .collect::<Vec<_>>();
+
+        if closest.is_empty() {
+            return None;
+        }
+
+        Some(FuzzyMatch {
+            score: closest
+                .iter()
+                .map(|(_, _, similarity)| *similarity)
+                .fold(1.0, f64::min),
+            variants: closest.into_iter().map(|(j, span, _)| (j, span)).collect(),
+        })
+    }
+
+    pub fn is_fuzzy(&self) -> bool {
+        self.highlights()
+            .iter()
+            .any(|term| term.mode == MatchMode::Fuzzy)
+    }
+
+    /// every term of the query
+    pub fn terms_mut(&mut self) -> Vec<&mut Term> {
+        match self {
+            Query::Term(term) => vec![term],
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter_mut().flat_map(Query::terms_mut).collect()
+            }
+            Query::Not(query) => query.terms_mut(),
+        }
+    }
+
+    /// normalize the text and the columns of the terms the way the cells are. A regex is left
+    /// as it's written, it only ignores case when the cells are folded
+    pub fn normalize(&mut self, normalization: &Normalization) -> Result<()> {
+        if normalization.is_none() {
+            return Ok(());
+        }
+
+        for term in self.terms_mut() {
+            term.normalize(normalization)?;
+        }
+
+        Ok(())
+    }
+
+    /// the terms a matched row can have, the ones under a `NOT` can't be in it
+    pub fn highlights(&self) -> Vec<&Term> {
+        match self {
+            Query::Term(term) if term.mode.is_text() && !term.text.is_empty() => vec![term],
+            Query::Term(_) | Query::Not(_) => vec![],
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().flat_map(Query::highlights).collect()
+            }
+        }
+    }
+}
+
+impl Term {
+    /// a term, the text of a regex one has to compile and the one of a comparison has to be a
+    /// number or a date
+    pub fn new(column: Option<String>, text: String, mode: MatchMode) -> Result<Self> {
+        let regex = if mode == MatchMode::Regex {
+            Some(compile(&text, false)?)
+        } else {
+            None
+        };
+
+        let values = match mode {
+            MatchMode::Greater
+            | MatchMode::GreaterOrEqual
+            | MatchMode::Less
+            | MatchMode::LessOrEqual => vec![value(&text)?],
+            MatchMode::Between => {
+                let (low, high) = text
+                    .split_once("..")
+                    .ok_or_else(|| invalid(format!("{:?} isn't a range like `1..10`", text)))?;
+                let (low, high) = (value(low)?, value(high)?);
+
+                if low.compare(&high).is_none() {
+                    return Err(invalid(format!(
+                        "the ends of {:?} aren't both numbers or both dates",
+                        text
+                    )));
+                }
+                vec![low, high]
+            }
+            _ => vec![],
+        };
+
+        Ok(Term {
+            column,
+            text,
+            mode,
+            fuzziness: Fuzziness::default(),
+            values,
+            regex,
+        })
+    }
+
+    fn normalize(&mut self, normalization: &Normalization) -> Result<()> {
+        if let Some(column) = &mut self.column {
+            *column = normalization.apply(column).text;
+        }
+
+        if self.regex.is_some() {
+            self.regex = Some(compile(&self.text, normalization.case)?);
+        } else if self.mode.is_text() {
+            self.text = normalization.apply(&self.text).text;
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, text: &[impl AsRef<str>], cells: &[Cell], headers: &[String]) -> bool {
+        self.cells_to_check(text, headers)
+            .any(|(j, text)| match self.mode {
+                MatchMode::IsEmpty => text.trim().is_empty(),
+                MatchMode::IsNotEmpty => !text.trim().is_empty(),
+                mode if !mode.is_text() => cells.get(j).is_some_and(|cell| self.compare(cell)),
+                _ => self.is_match(text),
+            })
+    }
+
+    /// whether the value of a cell is on the right side of the values of a comparison
+    fn compare(&self, cell: &Cell) -> bool {
+        let Some(value) = Value::of_cell(cell) else {
+            return false;
+        };
+        let after = |bound: &Value| matches!(value.compare(bound), Some(Ordering::Greater));
+        let before = |bound: &Value| matches!(value.compare(bound), Some(Ordering::Less));
+        let equal = |bound: &Value| matches!(value.compare(bound), Some(Ordering::Equal));
+
+        match (self.mode, self.values.as_slice()) {
+            (MatchMode::Greater, [bound]) => after(bound),
+            (MatchMode::GreaterOrEqual, [bound]) => after(bound) || equal(bound),
+            (MatchMode::Less, [bound]) => before(bound),
+            (MatchMode::LessOrEqual, [bound]) => before(bound) || equal(bound),
+            (MatchMode::Between, [low, high]) => {
+                (after(low) || equal(low)) && (before(high) || equal(high))
+            }
+            _ => false,
+        }
+    }
+
+    /// whether the term is looked for under a header, given its key (see [`header_key`]). A term
+    /// without a column is looked for under all of them
+    pub fn looks_in(&self, header: &str) -> bool {
+        self.column
+            .as_deref()
+            .is_none_or(|column| header_key(column) == header)
+    }
+
+    /// the cells of a row under the column of the term, along with their index
+    fn cells_to_check<'a>(
+        &self,
+        cells: &'a [impl AsRef<str>],
+        headers: &'a [String],
+    ) -> impl Iterator<Item = (usize, &'a str)> {
+        let column = self.column.as_deref().map(header_key);
+
+        cells
+            .iter()
+            .enumerate()
+            .filter(move |(j, _)| match &column {
+                Some(column) => headers.get(*j) == Some(column),
+                None => true,
+            })
+            .map(|(j, cell)| (j, cell.as_ref()))
+    }
+
+    fn is_match(&self, cell: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(cell),
+            None => match self.mode {
+                MatchMode::Exact => cell == self.text,
+                MatchMode::StartsWith => cell.starts_with(&self.text),
+                MatchMode::EndsWith => cell.ends_with(&self.text),
+                MatchMode::Fuzzy => self.closest(cell).is_some(),
+                MatchMode::Contains | MatchMode::Regex => cell.contains(&self.text),
+                // these need the typed cell, see `matches`
+                _ => false,
+            },
+        }
+    }
+
+    /// the part of a cell a fuzzy term is closest to, out of the whole cell and every run of as
+    /// many words as the term has, along with how similar it is
+    fn closest(&self, cell: &str) -> Option<(Range<usize>, f64)> {
+        let words = cell
+            .split_whitespace()
+            .map(|word| {
+                let start = word.as_ptr() as usize - cell.as_ptr() as usize;
+                start..start + word.len()
+            })
+            .collect::<Vec<_>>();
+        let width = self.text.split_whitespace().count().max(1);
+
+        std::iter::once(0..cell.len())
+            .chain(
+                words
+                    .windows(width)
+                    .map(|run| run[0].start..run[width - 1].end),
+            )
+            .filter_map(|span| {
+                let part = &cell[span.clone()];
+                let similarity = normalized_levenshtein(&self.text, part);
+
+                self.fuzziness
+                    .accepts(levenshtein(&self.text, part), similarity)
+                    .then_some((span, similarity))
+            })
+            // the first of the closest ones, the whole cell before its words
+            .reduce(|best, next| if next.1 > best.1 { next } else { best })
+    }
+
+    /// the cell of a row a fuzzy term is closest to, with the part of it and how similar it is
+    fn closest_in_row(
+        &self,
+        cells: &[impl AsRef<str>],
+        headers: &[String],
+    ) -> Option<(usize, Range<usize>, f64)> {
+        self.cells_to_check(cells, headers)
+            .filter_map(|(j, cell)| {
+                self.closest(cell)
+                    .map(|(span, similarity)| (j, span, similarity))
+            })
+            .reduce(|best, next| if next.2 > best.2 { next } else { best })
+    }
+
+    /// where the term is in a cell, in bytes. Empty regex matches aren't anything to show
+    pub fn spans(&self, cell: &str) -> Vec<Range<usize>> {
+        if let Some(regex) = &self.regex {
+            return regex
+                .find_iter(cell)
+                .filter(|found| !found.is_empty())
+                .map(|found| found.range())
+                .collect();
+        }
+
+        if !self.is_match(cell) {
+            return vec![];
+        }
+
+        let length = self.text.len();
+        let span = match self.mode {
+            MatchMode::Exact => 0..cell.len(),
+            MatchMode::Fuzzy => match self.closest(cell) {
+                Some((span, _)) => span,
+                None => return vec![],
+            },
+            MatchMode::StartsWith => 0..length,
+            MatchMode::EndsWith => cell.len() - length..cell.len(),
+            MatchMode::Contains | MatchMode::Regex => {
+                return cell
+                    .match_indices(&self.text)
+                    .map(|(start, _)| start..start + length)
+                    .collect();
+            }
+            _ => return vec![],
+        };
+
+        vec![span]
+    }
+}
+
+impl Fuzziness {
+    pub fn new(distance: Option<usize>, similarity: Option<f64>) -> Result<Self> {
+        if let Some(similarity) = similarity {
+            if !(0.0..=1.0).contains(&similarity) {
+                return Err(invalid(format!(
+                    "a similarity of {} isn't between 0 and 1",
+                    similarity
+                )));
+            }
+        }
+
+        Ok(Fuzziness {
+            distance,
+            similarity,
+        })
+    }
+
+    /// the fuzziness after the `~` of a term, `~2` for the edits and `~0.8` for the similarity.
+    /// Nothing when it isn't a number, the `~` is part of the text then
+    fn parse(text: &str) -> Result<Option<Self>> {
+        if text.is_empty() {
+            return Ok(Some(Fuzziness::default()));
+        }
+
+        if let Ok(distance) = text.parse::<usize>() {
+            return Fuzziness::new(Some(distance), None).map(Some);
+        }
+
+        match text.parse::<f64>() {
+            Ok(similarity) => Fuzziness::new(None, Some(similarity)).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn accepts(&self, distance: usize, similarity: f64) -> bool {
+        let distance = match (self.distance, self.similarity) {
+            (Some(most), _) => distance <= most,
+            (None, Some(_)) => true,
+            (None, None) => distance <= 2,
+        };
+
+        distance && self.similarity.is_none_or(|least| similarity >= least)
+    }
+}
+
+/// the number or the date of a comparison
+fn value(text: &str) -> Result<Value> {
+    Value::parse(text).ok_or_else(|| invalid(format!("{:?} isn't a number or a date", text.trim())))
+}
+
+fn compile(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| invalid(format!("{:?} isn't a valid regex: {}", pattern, e)))
+}
+
+/// where any of the terms are in a cell, overlapping spans joined in one
+pub fn highlight_spans(terms: &[&Term], cell: &str) -> Vec<Range<usize>> {
+    join_spans(terms.iter().flat_map(|term| term.spans(cell)).collect())
+}
+
+/// the spans in order, the ones that overlap or repeat joined in one
+pub fn join_spans(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|span| span.start);
+
+    let mut joined: Vec<Range<usize>> = vec![];
+    for span in spans {
+        match joined.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => joined.push(span),
+        }
+    }
+
+    joined
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// a bare word or quoted text, only bare words can be operators
+    Text {
+        text: String,
+        quoted: bool,
+    },
+    /// the text of a `/regex/`
+    Pattern(String),
+    Colon,
+    Equals,
+    /// `>`, `>=`, `<` or `<=`
+    Compare {
+        mode: MatchMode,
+        symbol: &'static str,
+    },
+    Open,
+    Close,
+}
+
+impl Token {
+    fn is_operator(&self, operator: &str) -> bool {
+        matches!(self, Token::Text { text, quoted: false } if text == operator)
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Text { text, .. } => write!(f, "{:?}", text),
+            Token::Pattern(pattern) => write!(f, "/{}/", pattern),
+            Token::Colon => write!(f, "`:`"),
+            Token::Equals => write!(f, "`=`"),
+            Token::Compare { symbol, .. } => write!(f, "`{}`", symbol),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut chars = query.chars().peekable();
+    let mut tokens = vec![];
+
+    while let Some(c) = chars.peek().copied() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ':' | '=' | '(' | ')' => {
+                chars.next();
+                tokens.push(match c {
+                    ':' => Token::Colon,
+                    '=' => Token::Equals,
+                    '(' => Token::Open,
+                    _ => Token::Close,
+                });
+            }
+            '>' | '<' => {
+                chars.next();
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                tokens.push(match (c, or_equal) {
+                    ('>', false) => Token::Compare {
+                        mode: MatchMode::Greater,
+                        symbol: ">",
+                    },
+                    ('>', true) => Token::Compare {
+                        mode: MatchMode::GreaterOrEqual,
+                        symbol: ">=",
+                    },
+                    (_, false) => Token::Compare {
+                        mode: MatchMode::Less,
+                        symbol: "<",
+                    },
+                    (_, true) => Token::Compare {
+                        mode: MatchMode::LessOrEqual,
+                        symbol: "<=",
+                    },
+                });
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Text {
+                    text: quoted(&mut chars, '"')?,
+                    quoted: true,
+                });
+            }
+            '/' => {
+                chars.next();
+                tokens.push(Token::Pattern(quoted(&mut chars, '/')?));
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(c) = chars.peek().copied() {
+                    if c.is_whitespace() || matches!(c, ':' | '=' | '<' | '>' | '(' | ')' | '"') {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+
+                tokens.push(Token::Text {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// the rest of a text quoted in `"` or `/`, a backslash in front of the quote makes it part of
+/// the text. A regex keeps the rest of its backslashes, `\\` is a backslash in a `"` quote
+fn quoted(chars: &mut Peekable<Chars<'_>>, quote: char) -> Result<String> {
+    let mut text = String::new();
+
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => return Ok(text),
+            Some('\\') if chars.peek() == Some(&quote) => text.extend(chars.next()),
+            Some('\\') if quote == '"' && chars.peek() == Some(&'\\') => {
+                text.extend(chars.next());
+            }
+            Some(c) => text.push(c),
+            None => return Err(invalid(format!("the quote of {:?} isn't closed", text))),
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut queries = vec![self.and()?];
+
+        while self.peek().is_some_and(|token| token.is_operator("OR")) {
+            self.next();
+            queries.push(self.and()?);
+        }
+
+        Ok(join_all(queries, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut queries = vec![self.unary()?];
+
+        loop {
+            match self.peek() {
+                Some(token) if token.is_operator("AND") => {
+                    self.next();
+                }
+                // a term right after another one is needed too
+                Some(Token::Text { .. } | Token::Pattern(_) | Token::Open)
+                    if !self.peek_is("OR") => {}
+                _ => break,
+            }
+
+            queries.push(self.unary()?);
+        }
+
+        Ok(join_all(queries, Query::And))
+    }
+
+    fn peek_is(&self, operator: &str) -> bool {
+        self.peek().is_some_and(|token| token.is_operator(operator))
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        if self.peek_is("NOT") {
+            self.next();
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+
+        match self.next().cloned() {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(invalid("a `(` isn't closed")),
+                }
+            }
+            Some(Token::Text { text, quoted }) if quoted || !is_operator(&text) => {
+                self.term(text, quoted)
+            }
+            Some(Token::Pattern(pattern)) => {
+                Term::new(None, pattern, MatchMode::Regex).map(Query::Term)
+            }
+            Some(token) => Err(invalid(format!("expected a term instead of {}", token))),
+            None => Err(invalid("the query ends where a term was expected")),
+        }
+    }
+
+    /// a term, `text` is its column if a `:`, `=` or a comparison comes after it. Only text can
+    /// come after the last two, a `=""` is an empty cell
+    fn term(&mut self, text: String, quoted: bool) -> Result<Query> {
+        let mode = match self.peek() {
+            Some(Token::Colon) => None,
+            Some(Token::Equals) => Some(MatchMode::Exact),
+            Some(Token::Compare { mode, .. }) => Some(*mode),
+            _ => return text_term(None, text, quoted),
+        };
+        self.next();
+
+        let column = Some(text.clone());
+        match (mode, self.next().cloned()) {
+            (
+                Some(MatchMode::Exact),
+                Some(Token::Text {
+                    text: value,
+                    quoted,
+                }),
+            ) => {
+                let mode = if quoted && value.is_empty() {
+                    MatchMode::IsEmpty
+                } else {
+                    MatchMode::Exact
+                };
+
+                Term::new(column, value, mode).map(Query::Term)
+            }
+            (Some(mode), Some(Token::Text { text: value, .. })) => {
+                Term::new(column, value, mode).map(Query::Term)
+            }
+            (
+                None,
+                Some(Token::Text {
+                    text: value,
+                    quoted,
+                }),
+            ) => text_term(column, value, quoted),
+            (None, Some(Token::Pattern(pattern))) => {
+                Term::new(column, pattern, MatchMode::Regex).map(Query::Term)
+            }
+            (Some(_), Some(Token::Pattern(_))) => Err(invalid(format!(
+                "the {:?} column can only have a regex after a `:`",
+                text
+            ))),
+            _ => Err(invalid(format!(
+                "the {:?} column is missing what to look for",
+                text
+            ))),
+        }
+    }
+}
+
+/// the term of a text, a `*` on one side of a bare word leaves that side open and a `~` after it
+/// makes it fuzzy. Two numbers or dates with `..` in between are a range when there's a column
+fn text_term(column: Option<String>, text: String, quoted: bool) -> Result<Query> {
+    let range = text
+        .split_once("..")
+        .is_some_and(|(low, high)| Value::parse(low).is_some() && Value::parse(high).is_some());
+    if column.is_some() && range {
+        return Term::new(column, text, MatchMode::Between).map(Query::Term);
+    }
+
+    if quoted {
+        return Term::new(column, text, MatchMode::Contains).map(Query::Term);
+    }
+
+    if let Some((word, fuzziness)) = text.rsplit_once('~') {
+        if let Some(fuzziness) = Fuzziness::parse(fuzziness)? {
+            let mut term = Term::new(column, word.to_string(), MatchMode::Fuzzy)?;
+            term.fuzziness = fuzziness;
+
+            return Ok(Query::Term(term));
+        }
+    }
+
+    let (text, mode) = match (text.strip_prefix('*'), text.strip_suffix('*')) {
+        (Some(rest), Some(_)) if rest.ends_with('*') => {
+            (rest[..rest.len() - 1].to_string(), MatchMode::Contains)
+        }
+        (Some(rest), _) => (rest.to_string(), MatchMode::EndsWith),
+        (None, Some(rest)) => (rest.to_string(), MatchMode::StartsWith),
+        (None, None) => (text, MatchMode::Contains),
+    };
+
+    Term::new(column, text, mode).map(Query::Term)
+}
+
+fn is_operator(text: &str) -> bool {
+    matches!(text, "AND" | "OR" | "NOT")
+}
+
+/// a single query as it is, otherwise all of them joined
+fn join_all(mut queries: Vec<Query>, join: fn(Vec<Query>) -> Query) -> Query {
+    if queries.len() == 1 {
+        queries.remove(0)
+    } else {
+        join(queries)
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidQuery {
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let query =
+            Query::parse(r#"Status:"Closed" AND (City:Cairo OR City=Giza) NOT late"#).unwrap();
+        let headers = ["status", "city", "notes"].map(String::from);
+        let typed = |text: &[&str]| {
+            text.iter()
+                .map(|text| Cell::from(*text))
+                .collect::<Vec<_>>()
+        };
+        let matches = |text: &[&str]| query.matches(text, &typed(text), &headers);
+
+        assert!(matches(&["Closed", "Cairo", ""]));
+        assert!(matches(&["Closed", "Giza", "on time"]));
+        assert!(!matches(&["Closed", "Giza City", ""]));
+        assert!(!matches(&["Open", "Cairo", ""]));
+        assert!(!matches(&["Closed", "Cairo", "late"]));
+        let highlights = query.highlights();
+        let highlights = highlights.iter().map(|term| term.text.as_str());
+        assert!(highlights.eq(["Closed", "Cairo", "Giza"]));
+
+        // AND binds tighter than OR
+        let query = Query::parse("a OR b c").unwrap();
+        assert!(matches!(&query, Query::Or(queries) if matches!(queries[1], Query::And(_))));
+
+        let query = Query::parse(r"Status:Cl* City:*ro Notes:/\d+ (days|weeks)/").unwrap();
+        let matches = |text: &[&str]| query.matches(text, &typed(text), &headers);
+        assert!(matches(&["Closed", "Cairo", "late 3 days, then 12 weeks"]));
+        assert!(!matches(&["Closed", "Cairo", "late"]));
+        assert!(!matches(&["Unclosed", "Cairo", "3 days"]));
+        let spans = highlight_spans(&query.highlights(), "late 3 days, then 12 weeks");
+        assert_eq!(spans, vec![5..11, 18..26]);
+
+        // one edit away from a word of the notes, the variant is the word it's closest to
+        let query = Query::parse("Notes:lte~1 OR Status:Clsoed~0.5").unwrap();
+        let found = query
+            .fuzzy_match(&["Open", "", "very late today"], &headers)
+            .unwrap();
+        assert_eq!(found.variants, vec![(2, 5..9)]);
+        assert_eq!(found.score, 0.75);
+        assert!(!query.matches(&["Open", "", "on time"], &[], &headers));
+
+        // the typed values are compared, text that is a number counts too
+        let query = Query::parse(r#"Qty>=5 Date:2023-07-01..2023-07-31 NOT Notes="""#).unwrap();
+        let headers = ["qty", "date", "notes"].map(String::from);
+        let date = |day| {
+            let date = chrono::NaiveDate::from_ymd_opt(2023, 7, day).unwrap();
+            Cell::DateTime(date.and_time(chrono::NaiveTime::MIN))
+        };
+        let matches = |cells: &[Cell]| {
+            let text = cells.iter().map(Cell::as_text).collect::<Vec<_>>();
+            query.matches(&text, cells, &headers)
+        };
+        assert!(matches(&[Cell::Float(5.0), date(31), "late".into()]));
+        assert!(matches(&["12".into(), date(1), "late".into()]));
+        assert!(!matches(&[Cell::Int(4), date(10), "late".into()]));
+        assert!(!matches(&[Cell::Int(9), date(10), " ".into()]));
+        assert!(!matches(&["many".into(), date(10), "late".into()]));
+
+        for query in [
+            "", "(a", "a)", "a AND", "City:", "\"a", "OR a", "/(/", "City=/a/", "a~2.5",
+            "Qty>many", "Qty<=/1/",
+        ] {
+            assert!(Query::parse(query).is_err(), "{:?} parsed", query);
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use crate::cell::{Cell, CellFormats, DateFormat};
 use crate::error::Result;
+use crate::formula::{write_missing_values, MissingValue};
 use anyhow::Context;
 use calamine::Dimensions;
 use std::{
@@ -7,11 +9,12 @@ use std::{
 };
 use tracing::{info, trace};
 
-use rust_xlsxwriter::{Format, Workbook};
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
 use zip::{write::SimpleFileOptions, ZipWriter};
 
 pub struct ReplyFiles {
     pub data: Vec<ReplyFile>,
+    pub date_format: DateFormat,
 }
 
 #[derive(Debug, Clone)]
@@ -24,7 +27,7 @@ pub enum MergeType {
 pub struct MergedLocation {
     /// (cut, original)
     pub dimensions: (Dimensions, Dimensions),
-    pub data: String,
+    pub data: Cell,
     pub variant: MergeType,
 }
 
@@ -32,9 +35,9 @@ pub struct MergedLocation {
 pub struct ReplyFile {
     pub name: PathBuf,
     pub last_modified: String,
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Vec<Cell>>,
     pub ext: String,
-    pub size: u32,
+    pub size: u64,
     pub cutting_rows: u32,
     pub merged_regions: Vec<Dimensions>,
     pub location_sheet_rows: Vec<Vec<String>>,
@@ -43,11 +46,16 @@ pub struct ReplyFile {
     pub sheet_name: String,
     pub checked: bool,
     pub reply: bool,
+    /// formulas without a cached value, only looked for when asked to
+    pub missing_values: Vec<MissingValue>,
 }
 
 impl ReplyFiles {
     pub fn new(data: Vec<ReplyFile>) -> Self {
-        ReplyFiles { data }
+        ReplyFiles {
+            data,
+            date_format: DateFormat::default(),
+        }
     }
 }
 
@@ -55,9 +63,9 @@ impl ReplyFile {
     pub fn new(
         name: String,
         last_modified: String,
-        rows: Vec<Vec<String>>,
+        rows: Vec<Vec<Cell>>,
         ext: String,
-        size: u32,
+        size: u64,
         cutting_rows: u32,
         merged_regions: Vec<Dimensions>,
         location_sheet_rows: Vec<Vec<String>>,
@@ -81,6 +89,7 @@ impl ReplyFile {
             sheet_name,
             checked,
             reply,
+            missing_values: vec![],
         }
     }
 }
@@ -94,6 +103,8 @@ impl ReplyFiles {
             let options =
                 SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
 
+            let formats = CellFormats::new(self.date_format.clone());
+
             for file in &self.data {
                 // write the actual file
                 let mut workbook = Workbook::new();
@@ -113,29 +124,29 @@ impl ReplyFiles {
                 // TODO: use the matrix function to be more concise
                 for (i, row) in file.rows.iter().enumerate() {
                     for (j, cell) in row.iter().enumerate() {
-                        worksheet
-                            .write_string(i as u32, j as u16, cell)
+                        cell.write(worksheet, i as u32, j as u16, &formats)
                             .context("error writing to the new worksheet")?;
                     }
                 }
 
                 if file.reply && !file.merged_locations.is_empty() {
-                    Self::write_loc_sheet(&mut workbook, &file.rows, &file.merged_locations)?;
+                    Self::write_loc_sheet(
+                        &mut workbook,
+                        &file.rows,
+                        &file.merged_locations,
+                        &formats,
+                    )?;
                 } else if !file.merged_locations.is_empty() {
                     for location in &file.merged_locations {
-                        worksheet
-                            .merge_range(
-                                location.dimensions.0.start.0,
-                                (location.dimensions.0.start.1) as u16,
-                                location.dimensions.0.end.0,
-                                (location.dimensions.0.end.1) as u16,
-                                location.data.as_str(),
-                                &Format::new(),
-                            )
+                        Self::write_merged_location(worksheet, location, &formats)
                             .context("error writing merged region")?;
                     }
                 }
 
+                if !file.missing_values.is_empty() {
+                    write_missing_values(&mut workbook, &file.missing_values)?;
+                }
+
                 let buf = workbook
                     .save_to_buffer()
                     .context("Failed to save workbook to buffer")?
@@ -153,6 +164,7 @@ impl ReplyFiles {
             let mut workbook = Workbook::new();
             let mut worksheet = workbook.add_worksheet();
             let file = &self.data[0];
+            let formats = CellFormats::new(self.date_format.clone());
 
             if file.rename {
                 worksheet = worksheet
@@ -167,26 +179,17 @@ impl ReplyFiles {
             // write manually to the worksheet
             for (i, row) in self.data[0].rows.iter().enumerate() {
                 for (j, cell) in row.iter().enumerate() {
-                    worksheet
-                        .write_string(i as u32, j as u16, cell)
+                    cell.write(worksheet, i as u32, j as u16, &formats)
                         .context("error writing to the new worksheet")?;
                 }
             }
 
             // write the location sheet
             if file.reply && !file.merged_locations.is_empty() {
-                Self::write_loc_sheet(&mut workbook, &file.rows, &file.merged_locations)?;
+                Self::write_loc_sheet(&mut workbook, &file.rows, &file.merged_locations, &formats)?;
             } else if !file.merged_locations.is_empty() {
                 for location in file.merged_locations.iter() {
-                    worksheet
-                        .merge_range(
-                            location.dimensions.0.start.0,
-                            (location.dimensions.0.start.1) as u16,
-                            location.dimensions.0.end.0,
-                            (location.dimensions.0.end.1) as u16,
-                            location.data.as_str(),
-                            &Format::new(),
-                        )
+                    Self::write_merged_location(worksheet, location, &formats)
                         // .context("error writing merged region")?;
                         .unwrap();
                     // FIXME: why errors and their BTs don't log?
@@ -201,6 +204,10 @@ impl ReplyFiles {
                 }
             }
 
+            if !file.missing_values.is_empty() {
+                write_missing_values(&mut workbook, &file.missing_values)?;
+            }
+
             let buf = workbook
                 .save_to_buffer()
                 .context("Failed to save workbook to buffer")?
@@ -212,8 +219,9 @@ impl ReplyFiles {
 
     pub fn write_loc_sheet(
         workbook: &mut Workbook,
-        data: &[Vec<String>],
+        data: &[Vec<Cell>],
         merged_locations: &[MergedLocation],
+        formats: &CellFormats,
     ) -> Result<()> {
         let sheet = workbook.add_worksheet();
         let sheet = sheet
@@ -222,23 +230,38 @@ impl ReplyFiles {
         let header = &data[0];
 
         // write the top header
-        sheet
-            .write_row(0, 0, header)
-            .context("error writing header")?;
+        for (j, cell) in header.iter().enumerate() {
+            cell.write(sheet, 0, j as u16, formats)
+                .context("error writing header")?;
+        }
 
         for location in merged_locations {
-            sheet
-                .merge_range(
-                    location.dimensions.0.start.0,
-                    (location.dimensions.0.start.1) as u16,
-                    location.dimensions.0.end.0,
-                    (location.dimensions.0.end.1) as u16,
-                    location.data.as_str(),
-                    &Format::new(),
-                )
-                .unwrap();
+            Self::write_merged_location(sheet, location, formats).unwrap();
         }
 
         Ok(())
     }
+
+    /// merge the cut region, `merge_range` only takes strings so the typed value is written over
+    /// the first cell afterwards
+    fn write_merged_location(
+        worksheet: &mut Worksheet,
+        location: &MergedLocation,
+        formats: &CellFormats,
+    ) -> std::result::Result<(), XlsxError> {
+        let (start, end) = (location.dimensions.0.start, location.dimensions.0.end);
+
+        worksheet.merge_range(
+            start.0,
+            start.1 as u16,
+            end.0,
+            end.1 as u16,
+            "",
+            &Format::new(),
+        )?;
+
+        location
+            .data
+            .write(worksheet, start.0, start.1 as u16, formats)
+    }
 }
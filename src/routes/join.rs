@@ -0,0 +1,28 @@
+use crate::error::Result;
+use crate::upload::UploadLimits;
+use crate::FilesMap;
+use axum::{
+    extract::{Extension, Multipart},
+    response::IntoResponse,
+};
+use tracing::info;
+
+#[utoipa::path(
+    get,
+    path = "/join",
+    responses(
+        (status = 200, description = "Join Excel files on key columns")
+    )
+)]
+pub async fn join_files(
+    Extension(limits): Extension<UploadLimits>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse> {
+    info!("Join requested. Processing files...");
+
+    let buffer = FilesMap::join_from_multipart(multipart, limits)
+        .await?
+        .write_to_buffer()?;
+
+    Ok(buffer)
+}
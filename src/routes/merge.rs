@@ -1,6 +1,10 @@
 use crate::error::Result;
+use crate::upload::UploadLimits;
 use crate::FilesMap;
-use axum::{extract::Multipart, response::IntoResponse};
+use axum::{
+    extract::{Extension, Multipart},
+    response::IntoResponse,
+};
 use tracing::info;
 
 // TODO: Add an #[instrument] for span tracing
@@ -11,11 +15,14 @@ use tracing::info;
         (status = 200, description = "Merge Excel files")
     )
 )]
-pub async fn merge_files(multipart: Multipart) -> Result<impl IntoResponse> {
+pub async fn merge_files(
+    Extension(limits): Extension<UploadLimits>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse> {
     info!("Merge requested. Processing files...");
 
     // create the files map object that will handle the merging
-    let buffer = FilesMap::merge_from_multipart(multipart)
+    let buffer = FilesMap::merge_from_multipart(multipart, limits)
         .await?
         .write_to_buffer()?;
 
@@ -1,3 +1,4 @@
+pub mod join;
 pub mod merge;
 pub mod search;
 pub mod reply;
@@ -1,11 +1,11 @@
-use crate::error::Error;
 use anyhow::Context;
-use std::io::Cursor;
 
+use crate::manifest::FileOptionsForm;
+use crate::upload::{Spooler, UploadLimits};
 use crate::{error::Result, reply::ReplyFiles};
-use crate::{get_file_extension, process_workbook, FilesMap};
+use crate::{get_file_extension, FilesMap};
 use axum::{
-    extract::{Multipart, Query},
+    extract::{Extension, Multipart, Query},
     response::IntoResponse,
 };
 use calamine::{Data, Dimensions, Reader};
@@ -13,7 +13,7 @@ use itertools::Itertools;
 use rust_xlsxwriter::Workbook;
 use serde::Deserialize;
 use size::Size;
-use tracing::{debug, info, trace, warn};
+use tracing::{debug, info};
 
 // TODO: Add an #[instrument] for span tracing
 #[utoipa::path(
@@ -23,11 +23,14 @@ use tracing::{debug, info, trace, warn};
         (status = 200, description = "Cell reply")
     )
 )]
-pub async fn cell_reply_files(multipart: Multipart) -> Result<impl IntoResponse> {
+pub async fn cell_reply_files(
+    Extension(limits): Extension<UploadLimits>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse> {
     info!("Cell reply requested. Processing files...");
 
     // create the files map object that will handle the "cell reply"
-    let buffer = FilesMap::reply_from_multipart(multipart)
+    let buffer = FilesMap::reply_from_multipart(multipart, limits)
         .await?
         .write_to_buffer(false)?;
 
@@ -42,12 +45,13 @@ pub async fn cell_reply_files(multipart: Multipart) -> Result<impl IntoResponse>
     )
 )]
 pub async fn cell_reply_file(
+    Extension(limits): Extension<UploadLimits>,
     multipart: Multipart,
 ) -> Result<impl IntoResponse> {
     info!("Cell reply requested (single). Processing file...");
 
     // create the files map object that will handle the "cell reply"
-    let buffer = FilesMap::reply_from_multipart(multipart)
+    let buffer = FilesMap::reply_from_multipart(multipart, limits)
         .await?
         .write_to_buffer(true)?;
 
@@ -61,115 +65,90 @@ pub async fn cell_reply_file(
         (status = 200, description = "Cell reply template")
     )
 )]
-pub async fn cell_reply_template(mut multipart: Multipart) -> Result<impl IntoResponse> {
+pub async fn cell_reply_template(
+    Extension(limits): Extension<UploadLimits>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse> {
     info!("Cell reply template requested. Processing file...");
 
     let mut files: ReplyFiles = ReplyFiles::new(vec![]);
-    let mut dates: Vec<String> = vec![];
-    let mut cutting_rows: Vec<u32> = vec![];
-    let mut sizes: Vec<u32> = vec![];
-    let mut checked: Vec<bool> = vec![];
-
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let content_type = field.content_type().map(str::to_owned);
-
+    let mut options = FileOptionsForm::default();
+    let mut spooler = Spooler::new(limits);
+    let mut uploads = vec![];
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .context("error reading the form")?
+    {
         let name = field.name().unwrap_or("unknown").to_owned();
-        let other_name = field.file_name().unwrap_or("unknown").to_owned();
-        let bytes = field.bytes().await.unwrap();
+        let other_name = field
+            .file_name()
+            .filter(|file_name| !file_name.is_empty())
+            .map(str::to_owned);
 
-        if name == "last-mod[]" {
-            let date = String::from_utf8(bytes.to_vec()).unwrap();
+        if let Some(other_name) = other_name {
+            println!("File name: {:?}", &name);
 
-            dates.push(date);
+            uploads.push(spooler.spool(field, name, other_name).await?);
 
             continue;
         }
 
-        if name == "size[]" {
-            if let Ok(size_str) = String::from_utf8(bytes.to_vec()) {
-                if let Ok(size) = size_str.parse::<u32>() {
-                    sizes.push(size);
-                }
-            }
-            trace!("Size values: {:?}", sizes);
-        }
-
-        if name == "cut-row[]" {
-            if let Ok(cut_row_str) = String::from_utf8(bytes.to_vec()) {
-                if let Ok(cut_row) = cut_row_str.parse::<u32>() {
-                    cutting_rows.push(cut_row);
-                }
-            }
-            trace!("Cutting-rows: {:?}", cutting_rows);
-        }
-
-        if name == "checked[]" {
-            if let Ok(checked_str) = String::from_utf8(bytes.to_vec()) {
-                match checked_str.as_str() {
-                    "Y" => checked.push(true),
-                    "N" | "" => checked.push(false),
-                    _ => checked.push(false),
-                }
-            }
-            trace!("checked: {:?}", checked);
+        let bytes = field.bytes().await.unwrap();
 
+        if options.read_field(&name, &bytes)? {
             continue;
         }
-
-        if let Some(content_type) = content_type {
-            let bytes = bytes.to_vec();
-            let reader = Cursor::new(bytes);
-
-            if content_type.as_str()
-                == "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
-                || content_type.as_str() == "application/vnd.ms-excel"
-            {
-                println!("File name: {:?}", &name);
-
-                let mut workbook = calamine::open_workbook_auto_from_rs(reader).unwrap();
-
-                if workbook.worksheets().len() > 1 {
-                    warn!("Has more than one sheet! Will only parse the first sheet.");
-                    return Err(Error::SheetLimitExceeded);
-                } else {
-                    debug!("Has only one sheet.")
-                }
-
-                let sheet_name = &workbook.worksheets()[0];
-
-                let other_name_clone = other_name.to_owned();
-                let ext = get_file_extension(&other_name_clone).unwrap();
-
-                files.data.push(crate::reply::ReplyFile::new(
-                    other_name.to_owned(),
-                    "unknown".to_string(),
-                    vec![],
-                    ext.to_string(),
-                    0,
-                    0,
-                    vec![],
-                    vec![],
-                    vec![],
-                    false,
-                    sheet_name.0.to_string(),
-                    false,
-                    false,
-                ));
-            }
-        }
     }
 
-    files.data.iter_mut().enumerate().for_each(|(i, file)| {
-        file.last_modified = dates[i].clone();
-        file.cutting_rows = cutting_rows[i].clone();
-        file.size = sizes[i].clone();
-        file.checked = checked[i].clone();
-    });
+    let file_names = uploads
+        .iter()
+        .map(|upload| upload.file_name.as_str())
+        .collect_vec();
+    let options = options.resolve(&file_names)?;
+    let uploads = spooler.expand(uploads, options).await?;
+
+    for (upload, options) in uploads {
+        // the template lists files, not sheets, so a multi-sheet workbook is still a
+        // single row
+        let sheet_name = if upload.is_delimited() {
+            upload.delimited_sheet_name()
+        } else {
+            let sheet_names = upload.open_workbook()?.sheet_names();
+            debug!("Sheets: {:?}", sheet_names);
+
+            sheet_names.first().cloned().unwrap_or_default()
+        };
+
+        let ext = get_file_extension(&upload.file_name).unwrap_or_default();
+
+        let mut file = crate::reply::ReplyFile::new(
+            upload.file_name.clone(),
+            "unknown".to_string(),
+            vec![],
+            ext.to_string(),
+            0,
+            0,
+            vec![],
+            vec![],
+            vec![],
+            false,
+            sheet_name,
+            false,
+            false,
+        );
+
+        if let Some(date) = options.last_mod {
+            file.last_modified = date;
+        }
+        file.cutting_rows = options.cut_row;
+        file.size = options.size;
+        file.checked = options.checked;
+        file.reply = options.reply;
 
-    dates.clear();
-    cutting_rows.clear();
-    sizes.clear();
-    checked.clear();
+        files.data.push(file);
+    }
 
     // dbg!(&files.data);
 
@@ -202,13 +181,8 @@ pub async fn cell_reply_template(mut multipart: Multipart) -> Result<impl IntoRe
             let size = Size::from_bytes(file.size).to_string();
             vec![
                 Data::String(i.to_string()),
-                Data::String(
-                    file.name
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string(),
-                ),
+                // files from an archive keep their folders
+                Data::String(file.name.with_extension("").to_string_lossy().to_string()),
                 Data::String(file.ext.to_string()),
                 Data::String(file.last_modified.to_string()),
                 Data::String(size),
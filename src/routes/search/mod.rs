@@ -1,6 +1,10 @@
 use crate::error::Result;
+use crate::upload::UploadLimits;
 use crate::FilesMap;
-use axum::{extract::Multipart, response::IntoResponse};
+use axum::{
+    extract::{Extension, Multipart},
+    response::IntoResponse,
+};
 use tracing::info;
 
 pub mod template_download;
@@ -13,11 +17,14 @@ pub mod template_download;
         (status = 200, description = "Query excel files")
     )
 )]
-pub async fn search_files(multipart: Multipart) -> Result<impl IntoResponse> {
+pub async fn search_files(
+    Extension(limits): Extension<UploadLimits>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse> {
     info!("Search requested. Processing files...");
 
     // create the files map object that will handle the merging
-    let buffer = FilesMap::search_from_multipart(multipart)
+    let buffer = FilesMap::search_from_multipart(multipart, limits)
         .await?
         .write_to_buffer()?;
 
@@ -5,19 +5,41 @@ use rust_xlsxwriter::{Color, Format, Workbook};
 use serde::Deserialize;
 use tracing::{info, debug};
 
+use crate::cell::{Cell, CellFormats, DateFormat};
 use crate::error::Result;
+use crate::mapping::{header_key, write_unmapped_headers, UnmappedHeader};
+use crate::normalize::Normalization;
+use crate::provenance::ProvenanceColumn;
+use crate::query::{highlight_spans, join_spans, MatchMode, Query, Term};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Search {
     pub data: String,
     pub title: Option<String>,
     pub intersections: Vec<Search>,
+    /// contains for the data and exact for the intersections when it isn't sent, the data of a
+    /// `between` is a range like `1..10`
+    #[serde(default)]
+    pub mode: Option<MatchMode>,
+    /// the most edits of a fuzzy condition
+    #[serde(default)]
+    pub distance: Option<usize>,
+    /// the least similarity of a fuzzy condition, from 0 to 1
+    #[serde(default)]
+    pub similarity: Option<f64>,
 }
 
 // TODO: Fix the visibility of structs like this
 pub struct SearchFiles {
-    pub rows: (Vec<Vec<String>>, Vec<String>),
-    pub conditions: Vec<Search>,
+    pub rows: (Vec<Vec<Cell>>, Vec<String>),
+    pub query: Query,
+    /// the cells are normalized like this before they're matched
+    pub normalization: Normalization,
+    pub date_format: DateFormat,
+    /// the headers that weren't in the column mapping, only when one was uploaded
+    pub unmapped_headers: Vec<UnmappedHeader>,
+    /// the columns each found row starts with
+    pub provenance: Vec<ProvenanceColumn>,
 }
 
 impl SearchFiles {
@@ -28,35 +50,40 @@ impl SearchFiles {
         let default = Format::default();
         let red = Format::new().set_font_color(Color::Red);
         let pink_bg = Format::new().set_background_color(Color::Pink);
+        let formats = CellFormats::new(self.date_format.clone());
 
         // write manually to the worksheet
         let headers = self.rows.0.remove(0);
+        let highlights = self.query.highlights();
+        // the columns are matched on the same keys the search used
+        let header_keys = headers
+            .iter()
+            .map(|header| header_key(&self.normalization.apply(&header.to_string()).text))
+            .collect_vec();
 
         // write intro headers
-        let intro_headers = [
-            "Date Modified",
-            "Number of Files",
-            "Series Number",
-            "Count Number",
-            "File Name",
-        ];
+        let intro_headers = self
+            .provenance
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect_vec();
 
         info!("Writing headers");
 
-        for (i, h) in intro_headers.into_iter().enumerate() {
+        for (i, h) in intro_headers.iter().enumerate() {
             worksheet
-                .write_string(0, i as u16, h)
+                .write_string(0, i as u16, *h)
                 .context("error writing header")?;
         }
 
-        for (i, h) in headers.iter().enumerate() {
-            if self.rows.1.contains(h) {
+        for (i, h) in headers.iter().map(Cell::to_string).enumerate() {
+            if self.rows.1.contains(&h) {
                 worksheet
-                    .write_string_with_format(0, (i + intro_headers.len()) as u16, h, &pink_bg)
+                    .write_string_with_format(0, (i + intro_headers.len()) as u16, &h, &pink_bg)
                     .context("error writing header")?;
             } else {
                 worksheet
-                    .write_string(0, (i + intro_headers.len()) as u16, h)
+                    .write_string(0, (i + intro_headers.len()) as u16, &h)
                     .context("error writing header")?;
             }
         }
@@ -66,33 +93,36 @@ impl SearchFiles {
         for (i, row) in self.rows.0.iter().enumerate() {
             for (j, cell) in row.iter().enumerate() {
                 let segment: Vec<(&Format, &str)>;
-                if j <= 4 {
-                    worksheet
-                        .write_string((i + 1) as u32, j as u16, cell)
+                if j < intro_headers.len() {
+                    cell.write(worksheet, (i + 1) as u32, j as u16, &formats)
                         .unwrap();
                     continue;
                 }
 
-                let vec = &self
-                    .conditions
-                    .iter()
-                    .filter(|c| cell.contains(&c.data))
-                    .map(|c| &c.data)
-                    .collect_vec();
-
-                let data = vec.get(0);
-
-                if let Some(d) = &data {
-                    let segment_string = Self::split_thing(cell, d);
-                    // println!("segment string is: {:?}", &segment_string);
-                    segment = segment_string
-                        .iter()
-                        .filter(|s| !s.is_empty())
-                        .map(|s| {
-                            if s == **d {
-                                (&red, d.as_str())
+                // only text can be highlighted, typed cells are written as they are
+                let Cell::String(cell) = cell else {
+                    if cell
+                        .write(worksheet, (i + 1) as u32, j as u16, &formats)
+                        .is_err()
+                    {
+                        debug!("error writing typed cell: {:?}", cell);
+                    }
+                    continue;
+                };
+
+                let header = header_keys
+                    .get(j - intro_headers.len())
+                    .map_or("", String::as_str);
+                let pieces = highlight_pieces(cell, header, &highlights, &self.normalization);
+
+                if pieces.iter().any(|(_, highlighted)| *highlighted) {
+                    segment = pieces
+                        .into_iter()
+                        .map(|(s, highlighted)| {
+                            if highlighted {
+                                (&red, s)
                             } else {
-                                (&default, *s)
+                                (&default, s)
                             }
                         })
                         .collect();
@@ -117,6 +147,10 @@ impl SearchFiles {
 
         worksheet.autofit();
 
+        if !self.unmapped_headers.is_empty() {
+            write_unmapped_headers(&mut workbook, &self.unmapped_headers)?;
+        }
+
         info!("saving to a buffer");
 
         let buf = workbook
@@ -130,25 +164,80 @@ impl SearchFiles {
         Ok(buf)
     }
 
-    pub fn write_to_vec(&self) -> Vec<Vec<String>> {
+    pub fn write_to_vec(&self) -> Vec<Vec<Cell>> {
         self.rows.0.clone()
     }
+}
+
+/// the pieces of a cell along with whether a term was found in them, empty ones left out. Only the
+/// terms looked for under the cell's `header` key count. They're found in the normalized cell, so
+/// the spans are joined again once they're back in the original one, a single character of it can
+/// be more than one normalized one (`¼` is `1⁄4`)
+fn highlight_pieces<'a>(
+    cell: &'a str,
+    header: &str,
+    terms: &[&Term],
+    normalization: &Normalization,
+) -> Vec<(&'a str, bool)> {
+    let terms = terms
+        .iter()
+        .copied()
+        .filter(|term| term.looks_in(header))
+        .collect_vec();
+    let normalized = normalization.apply(cell);
+    let spans = join_spans(
+        highlight_spans(&terms, &normalized.text)
+            .iter()
+            .map(|span| normalized.original(span))
+            .collect(),
+    );
+
+    let mut pieces = vec![];
+    let mut end = 0;
+
+    for span in spans {
+        pieces.push((&cell[end..span.start], false));
+        pieces.push((&cell[span.clone()], true));
+        end = span.end;
+    }
+    pieces.push((&cell[end..], false));
+
+    pieces.retain(|(piece, _)| !piece.is_empty());
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn split_thing<'a>(haystack: &'a str, needle: &str) -> Vec<&'a str> {
-        let Some((l, r)) = haystack.split_once(needle) else {
-            return vec![haystack];
+    #[test]
+    fn test_highlight_pieces() {
+        let query = Query::parse("1 OR 4").unwrap();
+        let normalization = Normalization {
+            nfkc: true,
+            ..Default::default()
         };
-        let m = &haystack[l.len()..][..needle.len()];
 
-        if l.is_empty() && r.is_empty() {
-            return vec![m];
-        }
-        if l.is_empty() {
-            return vec![m, r];
-        }
-        if r.is_empty() {
-            return vec![l, m];
-        }
-        vec![l, m, r]
+        // both terms are in the one `¼`
+        assert_eq!(
+            highlight_pieces("x ¼ y", "a", &query.highlights(), &normalization),
+            vec![("x ", false), ("¼", true), (" y", false)]
+        );
+        assert_eq!(
+            highlight_pieces("x ¼ y", "a", &query.highlights(), &Normalization::default()),
+            vec![("x ¼ y", false)]
+        );
+
+        // the row matched on its city, the name that happens to have the same text isn't marked
+        let query = Query::parse("City:Cairo").unwrap();
+        let normalization = Normalization::default();
+        assert_eq!(
+            highlight_pieces("New Cairo", "city", &query.highlights(), &normalization),
+            vec![("New ", false), ("Cairo", true)]
+        );
+        assert_eq!(
+            highlight_pieces("Cairo Ali", "name", &query.highlights(), &normalization),
+            vec![("Cairo Ali", false)]
+        );
     }
 }
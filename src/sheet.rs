@@ -0,0 +1,158 @@
+use std::io::{Read, Seek};
+
+use calamine::{Data, Range, Reader};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::error::{Error, Result};
+
+/// a sheet's name along with its cells
+pub type NamedSheet = (String, Range<Data>);
+
+/// Which sheets of a workbook should be read
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(from = "SheetValue")]
+pub enum SheetSelection {
+    /// only the first sheet, this is what we used to do before multi-sheet support
+    #[default]
+    First,
+    /// a sheet by its (zero based) position in the workbook
+    Index(usize),
+    /// a sheet by its name
+    Name(String),
+    /// every sheet in the workbook
+    All,
+}
+
+impl From<&str> for SheetSelection {
+    /// `""` is the first sheet, `"*"` or `"all"` is every sheet, anything else is either a
+    /// name or an index, a name always wins if a sheet is literally called "2" for example
+    fn from(value: &str) -> Self {
+        let value = value.trim();
+
+        if value.is_empty() {
+            SheetSelection::First
+        } else if value == "*" || value.eq_ignore_ascii_case("all") {
+            SheetSelection::All
+        } else {
+            SheetSelection::Name(value.to_string())
+        }
+    }
+}
+
+impl From<String> for SheetSelection {
+    fn from(value: String) -> Self {
+        SheetSelection::from(value.as_str())
+    }
+}
+
+/// how a sheet is sent in JSON, a number is always an index
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SheetValue {
+    Index(usize),
+    Text(String),
+}
+
+impl From<SheetValue> for SheetSelection {
+    fn from(value: SheetValue) -> Self {
+        match value {
+            SheetValue::Index(i) => SheetSelection::Index(i),
+            SheetValue::Text(text) => SheetSelection::from(text),
+        }
+    }
+}
+
+impl SheetSelection {
+    /// resolve the selection against the sheet names of a workbook
+    pub fn resolve(&self, file_name: &str, sheet_names: &[String]) -> Result<Vec<String>> {
+        let not_found = |sheet: String| Error::SheetNotFound {
+            file: file_name.to_string(),
+            sheet,
+        };
+
+        match self {
+            SheetSelection::First => sheet_names
+                .first()
+                .map(|name| vec![name.clone()])
+                .ok_or_else(|| not_found("0".to_string())),
+            SheetSelection::Index(i) => sheet_names
+                .get(*i)
+                .map(|name| vec![name.clone()])
+                .ok_or_else(|| not_found(i.to_string())),
+            SheetSelection::Name(name) => {
+                if sheet_names.contains(name) {
+                    return Ok(vec![name.clone()]);
+                }
+
+                // fall back to the index if it's a number
+                match name.parse::<usize>() {
+                    Ok(i) => SheetSelection::Index(i).resolve(file_name, sheet_names),
+                    Err(_) => Err(not_found(name.clone())),
+                }
+            }
+            SheetSelection::All => Ok(sheet_names.to_vec()),
+        }
+    }
+}
+
+/// read the selected sheets of a workbook, paired with their names
+pub fn read_sheets<R, RS>(
+    workbook: &mut R,
+    file_name: &str,
+    selection: &SheetSelection,
+) -> Result<Vec<NamedSheet>>
+where
+    R: Reader<RS>,
+    RS: Read + Seek,
+{
+    let sheet_names = selection.resolve(file_name, &workbook.sheet_names())?;
+    debug!("Reading sheets {:?} from {:?}", sheet_names, file_name);
+
+    let mut sheets = vec![];
+
+    for sheet_name in sheet_names {
+        match workbook.worksheet_range(&sheet_name) {
+            Ok(range) => sheets.push((sheet_name, range)),
+            // chart sheets and the like don't have a range, skip them
+            Err(e) => warn!(
+                "Skipping sheet {:?} of {:?}: {:?}",
+                sheet_name, file_name, e
+            ),
+        }
+    }
+
+    Ok(sheets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_selection() {
+        let names = vec!["Cover".to_string(), "Data".to_string(), "2".to_string()];
+
+        assert_eq!(
+            SheetSelection::from("").resolve("a.xlsx", &names).unwrap(),
+            vec!["Cover"]
+        );
+        assert_eq!(
+            SheetSelection::from("1").resolve("a.xlsx", &names).unwrap(),
+            vec!["Data"]
+        );
+        assert_eq!(
+            SheetSelection::from("2").resolve("a.xlsx", &names).unwrap(),
+            vec!["2"]
+        );
+        assert_eq!(
+            SheetSelection::from("all")
+                .resolve("a.xlsx", &names)
+                .unwrap(),
+            names
+        );
+        assert!(SheetSelection::from("Missing")
+            .resolve("a.xlsx", &names)
+            .is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+
+use crate::cell::Cell;
+use crate::error::{Error, Result};
+use crate::formula::parse_cell_name;
+use crate::File;
+
+/// the format of the `last-mod[]` dates the files are sorted by
+const DATE_FORMAT: &str = "%Y/%m/%d %H:%M";
+
+/// What the files are ordered by
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "by", rename_all = "lowercase")]
+pub enum SortKey {
+    /// the modified date sent for the file
+    Date,
+    /// the file name, numbers in it are compared as numbers
+    Name,
+    /// the size of the file in bytes
+    Size,
+    /// the order the files are listed in, files that aren't listed go after the ones that are
+    Order { files: Vec<String> },
+    /// the value of a cell of the sheet, e.g. a report date in `B2`
+    Cell { cell: String },
+}
+
+/// A sort key along with its direction, keys after the first break the ties of the ones before
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SortBy {
+    #[serde(flatten)]
+    pub key: SortKey,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl SortBy {
+    pub fn ascending(key: SortKey) -> Self {
+        SortBy {
+            key,
+            descending: false,
+        }
+    }
+
+    /// a JSON list of keys, `[{"by": "date", "descending": true}, {"by": "name"}]`
+    pub fn parse_list(bytes: &[u8]) -> Result<Vec<SortBy>> {
+        let keys: Vec<SortBy> = serde_json::from_slice(bytes).map_err(|e| Error::InvalidSort {
+            reason: e.to_string(),
+        })?;
+
+        for key in &keys {
+            if let SortKey::Cell { cell } = &key.key {
+                parse_cell_name(cell).ok_or_else(|| Error::InvalidSort {
+                    reason: format!("{:?} is not a cell", cell),
+                })?;
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// the cells the files are sorted by in the order of their keys, zero based so they can be
+/// looked up while parsing
+pub fn sort_cells(keys: &[SortBy]) -> Vec<(u32, u32)> {
+    keys.iter()
+        .filter_map(|key| match &key.key {
+            SortKey::Cell { cell } => parse_cell_name(cell),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What a file is compared by for a single key
+#[derive(Debug)]
+enum SortValue {
+    Date(NaiveDateTime),
+    Name(String),
+    Size(u64),
+    Position(usize),
+    Cell(Cell),
+}
+
+impl SortValue {
+    /// `cell` is the position of the key among the cell keys, the value it read is at the same one
+    fn of(file: &File, key: &SortKey, cell: usize) -> Result<Self> {
+        let value = match key {
+            SortKey::Date => {
+                let date = NaiveDateTime::parse_from_str(file.last_modified.trim(), DATE_FORMAT)
+                    .map_err(|_| Error::InvalidOptions {
+                        file: file.name.clone(),
+                        reason: format!(
+                            "can't sort by the modified date {:?}, it has to be YYYY/MM/DD HH:MM",
+                            file.last_modified
+                        ),
+                    })?;
+
+                SortValue::Date(date)
+            }
+            // only the last extension goes, folders and dots in the name stay
+            SortKey::Name => SortValue::Name(
+                Path::new(&file.name)
+                    .with_extension("")
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            SortKey::Size => SortValue::Size(file.size),
+            SortKey::Order { files } => SortValue::Position(
+                files
+                    .iter()
+                    .position(|name| *name == file.name || *name == file.display_name())
+                    .unwrap_or(files.len()),
+            ),
+            SortKey::Cell { .. } => {
+                SortValue::Cell(file.sort_values.get(cell).cloned().unwrap_or_default())
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortValue::Date(a), SortValue::Date(b)) => a.cmp(b),
+            (SortValue::Name(a), SortValue::Name(b)) => natural_cmp(a, b),
+            (SortValue::Size(a), SortValue::Size(b)) => a.cmp(b),
+            (SortValue::Position(a), SortValue::Position(b)) => a.cmp(b),
+            (SortValue::Cell(a), SortValue::Cell(b)) => cell_cmp(a, b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+/// sort the files by the keys, the sort is stable so files that tie keep their order. A date
+/// that can't be read is an error
+pub fn sort_files(files: &mut Vec<File>, keys: &[SortBy]) -> Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    // the position of every key among the cell keys
+    let cells = keys
+        .iter()
+        .scan(0, |cells, key| {
+            let cell = *cells;
+            if matches!(key.key, SortKey::Cell { .. }) {
+                *cells += 1;
+            }
+
+            Some(cell)
+        })
+        .collect::<Vec<_>>();
+
+    let mut sorted = std::mem::take(files)
+        .into_iter()
+        .map(|file| {
+            let values = keys
+                .iter()
+                .zip(&cells)
+                .map(|(key, cell)| SortValue::of(&file, &key.key, *cell))
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok((values, file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    sorted.sort_by(|(a, _), (b, _)| {
+        keys.iter()
+            .zip(a.iter().zip(b))
+            .map(|(key, (a, b))| {
+                if key.descending {
+                    a.cmp(b).reverse()
+                } else {
+                    a.cmp(b)
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+
+    *files = sorted.into_iter().map(|(_, file)| file).collect();
+
+    Ok(())
+}
+
+/// numbers go before dates, dates before text and empty cells go last, text is compared naturally
+fn cell_cmp(a: &Cell, b: &Cell) -> Ordering {
+    let (a, b) = (value_of(a), value_of(b));
+
+    match (a, b) {
+        (Cell::Int(_) | Cell::Float(_), Cell::Int(_) | Cell::Float(_)) => {
+            number(a).total_cmp(&number(b))
+        }
+        (Cell::DateTime(x), Cell::DateTime(y)) => x.cmp(y),
+        _ if rank(a) == rank(b) => natural_cmp(&a.as_text(), &b.as_text()),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// the cached value of a formula, any other cell as it is
+fn value_of(cell: &Cell) -> &Cell {
+    match cell {
+        Cell::Formula { value, .. } => value,
+        cell => cell,
+    }
+}
+
+fn rank(cell: &Cell) -> u8 {
+    match cell {
+        Cell::Int(_) | Cell::Float(_) => 0,
+        Cell::DateTime(_) => 1,
+        cell if cell.is_empty() => 3,
+        _ => 2,
+    }
+}
+
+fn number(cell: &Cell) -> f64 {
+    match cell {
+        Cell::Int(i) => *i as f64,
+        Cell::Float(f) => *f,
+        _ => 0.0,
+    }
+}
+
+/// compare text the way people expect, "file9" before "file10". Runs of digits (arabic-indic
+/// ones too) are compared by their value, the rest by its lowercase characters
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    // the case only matters when nothing else does
+    folded_cmp(a, b).then_with(|| a.cmp(b))
+}
+
+fn folded_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if digit(x).is_some() && digit(y).is_some() => {
+                let x = digits(&mut a);
+                let y = digits(&mut b);
+
+                // without the leading zeros, a longer number is a larger one
+                let (x_value, y_value) = (trim_zeros(&x), trim_zeros(&y));
+                let ordering = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+                    .then_with(|| x.len().cmp(&y.len()));
+
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering.is_ne() {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// the value of an ascii, arabic-indic or extended arabic-indic digit
+fn digit(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' => Some(c as u8 - b'0'),
+        '\u{0660}'..='\u{0669}' => Some((c as u32 - 0x0660) as u8),
+        '\u{06F0}'..='\u{06F9}' => Some((c as u32 - 0x06F0) as u8),
+        _ => None,
+    }
+}
+
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Vec<u8> {
+    let mut digits = vec![];
+
+    while let Some(value) = chars.peek().copied().and_then(digit) {
+        digits.push(value);
+        chars.next();
+    }
+
+    digits
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let start = digits
+        .iter()
+        .position(|digit| *digit != 0)
+        .unwrap_or(digits.len());
+
+    &digits[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file;
+
+    #[test]
+    fn test_natural_sort() {
+        let mut names = vec![
+            "file10",
+            "File9",
+            "file9",
+            "file1",
+            "file010",
+            "تقرير ١٠",
+            "تقرير ٩",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+
+        assert_eq!(
+            names,
+            vec![
+                "file1",
+                "File9",
+                "file9",
+                "file10",
+                "file010",
+                "تقرير ٩",
+                "تقرير ١٠"
+            ]
+        );
+        assert_eq!(
+            natural_cmp(
+                "report 99999999999999999999",
+                "report 100000000000000000000"
+            ),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_cell_order() {
+        assert_eq!(cell_cmp(&Cell::Float(9.5), &Cell::Int(10)), Ordering::Less);
+        assert_eq!(cell_cmp(&Cell::Empty, &"a".into()), Ordering::Greater);
+    }
+
+    /// the names of the files sorted by a JSON list of keys
+    fn sorted(mut files: Vec<File>, keys: &str) -> Vec<String> {
+        let keys = SortBy::parse_list(keys.as_bytes()).unwrap();
+        sort_files(&mut files, &keys).unwrap();
+
+        files.into_iter().map(|file| file.name).collect()
+    }
+
+    #[test]
+    fn test_sort_by_date() {
+        let file = |name: &str, date: &str| {
+            let mut file = test_file(name, &[], &[]);
+            file.last_modified = date.to_string();
+            file
+        };
+        let files = || {
+            vec![
+                file("a.xlsx", "2023/02/01 00:00"),
+                file("b.xlsx", "2023/01/01 10:00"),
+                file("c.xlsx", "2023/03/01 00:00"),
+            ]
+        };
+
+        assert_eq!(
+            sorted(files(), r#"[{"by": "date", "descending": true}]"#),
+            ["c.xlsx", "a.xlsx", "b.xlsx"]
+        );
+
+        let mut files = files();
+        files[0].last_modified = "yesterday".to_string();
+        let keys = [SortBy::ascending(SortKey::Date)];
+        assert!(matches!(
+            sort_files(&mut files, &keys),
+            Err(Error::InvalidOptions { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sort_by_name() {
+        let files = ["file10.xlsx", "file9.xlsx", "file1.csv"]
+            .iter()
+            .map(|name| test_file(name, &[], &[]))
+            .collect();
+
+        assert_eq!(
+            sorted(files, r#"[{"by": "name"}]"#),
+            ["file1.csv", "file9.xlsx", "file10.xlsx"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_size() {
+        let files = [("a.xlsx", 30), ("b.xlsx", 10), ("c.xlsx", 20)]
+            .iter()
+            .map(|(name, size)| {
+                let mut file = test_file(name, &[], &[]);
+                file.size = *size;
+                file
+            })
+            .collect();
+
+        assert_eq!(
+            sorted(files, r#"[{"by": "size"}]"#),
+            ["b.xlsx", "c.xlsx", "a.xlsx"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_order() {
+        let files = ["a.xlsx", "b.xlsx", "c.xlsx"]
+            .iter()
+            .map(|name| test_file(name, &[], &[]))
+            .collect();
+
+        // the files that aren't listed keep their order after the listed ones
+        assert_eq!(
+            sorted(files, r#"[{"by": "order", "files": ["c.xlsx"]}]"#),
+            ["c.xlsx", "a.xlsx", "b.xlsx"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_cells() {
+        let file = |name: &str, values: Vec<Cell>| {
+            let mut file = test_file(name, &[], &[]);
+            file.sort_values = values;
+            file
+        };
+        let files = vec![
+            file("a.xlsx", vec![Cell::Int(2), Cell::Int(1)]),
+            file("b.xlsx", vec![Cell::Int(1), Cell::Int(5)]),
+            file("c.xlsx", vec![Cell::Int(2), Cell::Int(3)]),
+        ];
+        let keys =
+            r#"[{"by": "cell", "cell": "A1"}, {"by": "cell", "cell": "B2", "descending": true}]"#;
+
+        let cells = sort_cells(&SortBy::parse_list(keys.as_bytes()).unwrap());
+        assert_eq!(cells, vec![(0, 0), (1, 1)]);
+        assert_eq!(sorted(files, keys), ["b.xlsx", "c.xlsx", "a.xlsx"]);
+    }
+}
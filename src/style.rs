@@ -0,0 +1,560 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek};
+
+use anyhow::Context;
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rust_xlsxwriter::{Color, Format, FormatAlign, FormatBorder, FormatUnderline, Worksheet};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::error::Result;
+use crate::formula::parse_cell_name;
+
+/// the first number format id that isn't one of excel's built in ones
+const FIRST_CUSTOM_FORMAT: u32 = 164;
+/// the sides of a border in the order they're kept in
+const SIDES: [&str; 4] = ["left", "right", "top", "bottom"];
+
+/// The look of the main file carried over to the merged output, only xlsx files have one
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SheetStyle {
+    /// the format of each header cell, `None` for the ones that aren't styled
+    pub header: Vec<Option<Format>>,
+    /// the number format of each column, taken from the first row under the header
+    pub columns: Vec<Option<Format>>,
+    /// the width of each column in characters, `None` when it's the default one
+    pub widths: Vec<Option<f64>>,
+}
+
+impl SheetStyle {
+    /// read the style of a sheet of an xlsx workbook. `header_row` is zero based, the row under
+    /// it is the first data row, and `columns` is the first column along with how many there are
+    pub fn read<R: Read + Seek>(
+        reader: R,
+        sheet_name: &str,
+        header_row: u32,
+        columns: (u32, usize),
+    ) -> Result<SheetStyle> {
+        let mut archive = ZipArchive::new(reader).context("error opening the workbook")?;
+        let styles = Styles::read(&mut archive)?;
+        let Some(path) = sheet_path(&mut archive, sheet_name)? else {
+            return Ok(SheetStyle::default());
+        };
+
+        let (first, width) = columns;
+        let last = first + width as u32;
+        let mut header = vec![None; width];
+        let mut data = vec![None; width];
+        let mut widths = vec![None; width];
+        let (mut row, mut column) = (0, 0);
+
+        visit(&mut archive, &path, |parents, element| {
+            match (parents, element.name.as_str()) {
+                ([.., "cols"], "col") => {
+                    let min = element.number::<u32>("min").unwrap_or(1);
+                    let max = element.number::<u32>("max").unwrap_or(min);
+                    let Some(col_width) = element.number::<f64>("width") else {
+                        return true;
+                    };
+
+                    // excel pads the widths it saves, the writer pads them again
+                    for col in min.saturating_sub(1).max(first)..max.min(last) {
+                        widths[(col - first) as usize] = Some((col_width - 5.0 / 7.0).max(0.0));
+                    }
+                }
+                ([.., "sheetData"], "row") => {
+                    row = match element.number::<u32>("r") {
+                        Some(r) => r.saturating_sub(1),
+                        None => row + 1,
+                    };
+                    column = 0;
+
+                    // nothing past the first data row matters
+                    if row > header_row + 1 {
+                        return false;
+                    }
+                }
+                ([.., "row"], "c") => {
+                    if let Some((_, col)) = element.get("r").and_then(parse_cell_name) {
+                        column = col;
+                    }
+                    let xf = element.number::<usize>("s").unwrap_or(0);
+
+                    if (first..last).contains(&column) && xf > 0 {
+                        let j = (column - first) as usize;
+
+                        if row == header_row {
+                            header[j] = styles.format(xf);
+                        } else if row == header_row + 1 {
+                            data[j] = styles.num_format(xf);
+                        }
+                    }
+                    column += 1;
+                }
+                _ => {}
+            }
+
+            true
+        })?;
+
+        Ok(SheetStyle {
+            header,
+            columns: data,
+            widths,
+        })
+    }
+
+    /// the format of a cell of the merged rows, the header is the first row
+    pub fn format(&self, row: usize, column: usize) -> Option<&Format> {
+        let formats = if row == 0 {
+            &self.header
+        } else {
+            &self.columns
+        };
+
+        formats.get(column)?.as_ref()
+    }
+
+    /// set the widths of the columns, `offset` is where the first one goes
+    pub fn set_widths(&self, worksheet: &mut Worksheet, offset: u16) -> Result<()> {
+        for (j, width) in self.widths.iter().enumerate() {
+            if let Some(width) = width {
+                worksheet
+                    .set_column_width(offset + j as u16, *width)
+                    .context("error setting a column width")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An xml element, its name and the names of its attributes are without their prefixes
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+impl Element {
+    fn new(start: &BytesStart) -> Result<Self> {
+        let mut attributes = HashMap::new();
+
+        for attribute in start.attributes() {
+            let attribute = attribute.context("error reading an xml attribute")?;
+            let value = String::from_utf8_lossy(&attribute.value);
+            let value = unescape(&value).context("error reading an xml attribute")?;
+
+            attributes.insert(
+                String::from_utf8_lossy(attribute.key.local_name().as_ref()).to_string(),
+                value.to_string(),
+            );
+        }
+
+        Ok(Element {
+            name: String::from_utf8_lossy(start.local_name().as_ref()).to_string(),
+            attributes,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+
+    /// `<b/>` is on, `<b val="0"/>` is off
+    fn is_on(&self) -> bool {
+        !matches!(self.get("val"), Some("0" | "false"))
+    }
+
+    /// only colors given as rgb are carried over, theme and indexed ones aren't
+    fn color(&self) -> Option<Color> {
+        let rgb = self.get("rgb")?;
+
+        u32::from_str_radix(rgb.get(rgb.len().saturating_sub(6)..)?, 16)
+            .ok()
+            .map(Color::RGB)
+    }
+}
+
+/// go through the elements of a part of the workbook along with the names of the elements they're
+/// in, until `visit` returns false. A part that isn't there has no elements
+fn visit<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    path: &str,
+    mut visit: impl FnMut(&[&str], &Element) -> bool,
+) -> Result<()> {
+    let file = match archive.by_name(path) {
+        Err(ZipError::FileNotFound) => return Ok(()),
+        file => file.with_context(|| format!("error reading {}", path))?,
+    };
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    let mut parents: Vec<String> = vec![];
+    let mut buf = vec![];
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .with_context(|| format!("error reading {}", path))?;
+
+        match &event {
+            Event::Start(start) | Event::Empty(start) => {
+                let element = Element::new(start)?;
+                if !visit(&names(&parents), &element) {
+                    break;
+                }
+
+                // an empty element has no end, nothing is in it
+                if let Event::Start(_) = event {
+                    parents.push(element.name);
+                }
+            }
+            Event::End(_) => {
+                parents.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    Ok(())
+}
+
+fn names(parents: &[String]) -> Vec<&str> {
+    parents.iter().map(String::as_str).collect()
+}
+
+/// where the xml of a sheet is in the workbook, if it has such a sheet
+fn sheet_path<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    sheet_name: &str,
+) -> Result<Option<String>> {
+    let mut id = None;
+    visit(archive, "xl/workbook.xml", |_, element| {
+        if element.name == "sheet" && element.get("name") == Some(sheet_name) {
+            id = element.get("id").map(str::to_string);
+        }
+
+        id.is_none()
+    })?;
+
+    let Some(id) = id else {
+        return Ok(None);
+    };
+
+    let mut target = None;
+    visit(archive, "xl/_rels/workbook.xml.rels", |_, element| {
+        if element.name == "Relationship" && element.get("Id") == Some(id.as_str()) {
+            target = element.get("Target").map(str::to_string);
+        }
+
+        target.is_none()
+    })?;
+
+    // targets are relative to the workbook unless they start at the root
+    Ok(target.map(|target| match target.strip_prefix('/') {
+        Some(target) => target.to_string(),
+        None => format!("xl/{}", target),
+    }))
+}
+
+#[derive(Default)]
+struct Font {
+    bold: bool,
+    italic: bool,
+    underline: Option<FormatUnderline>,
+    strikethrough: bool,
+    size: Option<f64>,
+    color: Option<Color>,
+    name: Option<String>,
+}
+
+#[derive(Default)]
+struct Side {
+    style: Option<FormatBorder>,
+    color: Option<Color>,
+}
+
+/// A cell format of the workbook, the indices point into the lists of the styles
+#[derive(Default)]
+struct Xf {
+    num_format: u32,
+    font: usize,
+    fill: usize,
+    border: usize,
+    horizontal: Option<FormatAlign>,
+    vertical: Option<FormatAlign>,
+    wrap: bool,
+}
+
+/// The parts of `xl/styles.xml` that are carried over
+#[derive(Default)]
+struct Styles {
+    num_formats: HashMap<u32, String>,
+    fonts: Vec<Font>,
+    /// the color of the solid fills, other patterns aren't carried over
+    fills: Vec<Option<Color>>,
+    borders: Vec<[Side; 4]>,
+    xfs: Vec<Xf>,
+}
+
+impl Styles {
+    fn read<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Self> {
+        let mut styles = Styles::default();
+
+        // only the fills with a solid pattern have a color
+        let mut solid = false;
+
+        visit(archive, "xl/styles.xml", |parents, element| {
+            match (parents, element.name.as_str()) {
+                ([.., "numFmts"], "numFmt") => {
+                    if let (Some(id), Some(code)) =
+                        (element.number("numFmtId"), element.get("formatCode"))
+                    {
+                        styles.num_formats.insert(id, code.to_string());
+                    }
+                }
+                ([.., "fonts"], "font") => styles.fonts.push(Font::default()),
+                ([.., "fonts", "font"], name) => {
+                    let Some(font) = styles.fonts.last_mut() else {
+                        return true;
+                    };
+
+                    match name {
+                        "b" => font.bold = element.is_on(),
+                        "i" => font.italic = element.is_on(),
+                        "strike" => font.strikethrough = element.is_on(),
+                        "u" => {
+                            font.underline = match element.get("val") {
+                                None | Some("single") => Some(FormatUnderline::Single),
+                                Some("double") => Some(FormatUnderline::Double),
+                                Some("singleAccounting") => Some(FormatUnderline::SingleAccounting),
+                                Some("doubleAccounting") => Some(FormatUnderline::DoubleAccounting),
+                                _ => None,
+                            }
+                        }
+                        "sz" => font.size = element.number("val"),
+                        "color" => font.color = element.color(),
+                        "name" => font.name = element.get("val").map(str::to_string),
+                        _ => {}
+                    }
+                }
+                ([.., "fills"], "fill") => styles.fills.push(None),
+                ([.., "fills", "fill"], "patternFill") => {
+                    solid = element.get("patternType") == Some("solid");
+                }
+                ([.., "fills", "fill", "patternFill"], "fgColor") => {
+                    if let (true, Some(fill)) = (solid, styles.fills.last_mut()) {
+                        *fill = element.color();
+                    }
+                }
+                ([.., "borders"], "border") => styles.borders.push(Default::default()),
+                ([.., "borders", "border"], name) => {
+                    let side = SIDES.iter().position(|side| *side == name);
+                    if let (Some(side), Some(border)) = (side, styles.borders.last_mut()) {
+                        border[side].style = element.get("style").and_then(border_style);
+                    }
+                }
+                ([.., "borders", "border", side], "color") => {
+                    let side = SIDES.iter().position(|name| name == side);
+                    if let (Some(side), Some(border)) = (side, styles.borders.last_mut()) {
+                        border[side].color = element.color();
+                    }
+                }
+                ([.., "cellXfs"], "xf") => styles.xfs.push(Xf {
+                    num_format: element.number("numFmtId").unwrap_or(0),
+                    font: element.number("fontId").unwrap_or(0),
+                    fill: element.number("fillId").unwrap_or(0),
+                    border: element.number("borderId").unwrap_or(0),
+                    ..Default::default()
+                }),
+                ([.., "cellXfs", "xf"], "alignment") => {
+                    if let Some(xf) = styles.xfs.last_mut() {
+                        xf.horizontal = element.get("horizontal").and_then(horizontal_align);
+                        xf.vertical = element.get("vertical").and_then(vertical_align);
+                        xf.wrap = matches!(element.get("wrapText"), Some("1" | "true"));
+                    }
+                }
+                _ => {}
+            }
+
+            true
+        })?;
+
+        Ok(styles)
+    }
+
+    /// the number format of a cell format, `None` for the general one
+    fn num_format(&self, xf: usize) -> Option<Format> {
+        let id = self.xfs.get(xf)?.num_format;
+
+        match self.num_formats.get(&id) {
+            Some(code) => Some(Format::new().set_num_format(code)),
+            None if id > 0 && id < FIRST_CUSTOM_FORMAT => {
+                Some(Format::new().set_num_format_index(id as u8))
+            }
+            None => None,
+        }
+    }
+
+    /// the whole look of a cell format, its font, fill, borders, alignment and number format
+    fn format(&self, xf: usize) -> Option<Format> {
+        let style = self.xfs.get(xf)?;
+        let mut format = self.num_format(xf).unwrap_or_default();
+
+        if let Some(font) = self.fonts.get(style.font) {
+            if font.bold {
+                format = format.set_bold();
+            }
+            if font.italic {
+                format = format.set_italic();
+            }
+            if font.strikethrough {
+                format = format.set_font_strikethrough();
+            }
+            if let Some(underline) = font.underline {
+                format = format.set_underline(underline);
+            }
+            if let Some(size) = font.size {
+                format = format.set_font_size(size);
+            }
+            if let Some(color) = font.color {
+                format = format.set_font_color(color);
+            }
+            if let Some(name) = &font.name {
+                format = format.set_font_name(name);
+            }
+        }
+
+        if let Some(Some(color)) = self.fills.get(style.fill) {
+            format = format.set_background_color(*color);
+        }
+
+        if let Some([left, right, top, bottom]) = self.borders.get(style.border) {
+            if let Some(border) = left.style {
+                format = format.set_border_left(border);
+            }
+            if let Some(color) = left.color {
+                format = format.set_border_left_color(color);
+            }
+            if let Some(border) = right.style {
+                format = format.set_border_right(border);
+            }
+            if let Some(color) = right.color {
+                format = format.set_border_right_color(color);
+            }
+            if let Some(border) = top.style {
+                format = format.set_border_top(border);
+            }
+            if let Some(color) = top.color {
+                format = format.set_border_top_color(color);
+            }
+            if let Some(border) = bottom.style {
+                format = format.set_border_bottom(border);
+            }
+            if let Some(color) = bottom.color {
+                format = format.set_border_bottom_color(color);
+            }
+        }
+
+        for align in [style.horizontal, style.vertical].into_iter().flatten() {
+            format = format.set_align(align);
+        }
+        if style.wrap {
+            format = format.set_text_wrap();
+        }
+
+        Some(format)
+    }
+}
+
+fn border_style(style: &str) -> Option<FormatBorder> {
+    let border = match style {
+        "thin" => FormatBorder::Thin,
+        "medium" => FormatBorder::Medium,
+        "dashed" => FormatBorder::Dashed,
+        "dotted" => FormatBorder::Dotted,
+        "thick" => FormatBorder::Thick,
+        "double" => FormatBorder::Double,
+        "hair" => FormatBorder::Hair,
+        "mediumDashed" => FormatBorder::MediumDashed,
+        "dashDot" => FormatBorder::DashDot,
+        "mediumDashDot" => FormatBorder::MediumDashDot,
+        "dashDotDot" => FormatBorder::DashDotDot,
+        "mediumDashDotDot" => FormatBorder::MediumDashDotDot,
+        "slantDashDot" => FormatBorder::SlantDashDot,
+        _ => return None,
+    };
+
+    Some(border)
+}
+
+fn horizontal_align(align: &str) -> Option<FormatAlign> {
+    let align = match align {
+        "left" => FormatAlign::Left,
+        "center" => FormatAlign::Center,
+        "right" => FormatAlign::Right,
+        "fill" => FormatAlign::Fill,
+        "justify" => FormatAlign::Justify,
+        "centerContinuous" => FormatAlign::CenterAcross,
+        "distributed" => FormatAlign::Distributed,
+        _ => return None,
+    };
+
+    Some(align)
+}
+
+fn vertical_align(align: &str) -> Option<FormatAlign> {
+    let align = match align {
+        "top" => FormatAlign::Top,
+        "center" => FormatAlign::VerticalCenter,
+        "bottom" => FormatAlign::Bottom,
+        "justify" => FormatAlign::VerticalJustify,
+        "distributed" => FormatAlign::VerticalDistributed,
+        _ => return None,
+    };
+
+    Some(align)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_xlsxwriter::Workbook;
+
+    #[test]
+    fn test_read_sheet_style() {
+        let bold = Format::new()
+            .set_bold()
+            .set_background_color(Color::RGB(0xFFFF00));
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet().set_name("Data").unwrap();
+        worksheet.write_string(1, 1, "Name").unwrap();
+        worksheet
+            .write_string_with_format(1, 2, "Qty", &bold)
+            .unwrap();
+        worksheet.write_string(2, 1, "Ali").unwrap();
+        worksheet
+            .write_number_with_format(2, 2, 3.5, &Format::new().set_num_format("0.000"))
+            .unwrap();
+        worksheet.set_column_width(1, 20).unwrap();
+        let buffer = workbook.save_to_buffer().unwrap();
+
+        let style = SheetStyle::read(std::io::Cursor::new(buffer), "Data", 1, (1, 2)).unwrap();
+
+        assert_eq!(style.header[0], None);
+        assert!(style.header[1].is_some());
+        assert_eq!(
+            style.columns,
+            vec![None, Some(Format::new().set_num_format("0.000"))]
+        );
+        assert_eq!(style.widths[0].map(f64::round), Some(20.0));
+        assert_eq!(style.widths[1], None);
+    }
+}
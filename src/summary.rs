@@ -0,0 +1,230 @@
+use anyhow::Context;
+use rust_xlsxwriter::Workbook;
+
+use crate::cell::Cell;
+use crate::dedup::DuplicateRow;
+use crate::error::Result;
+use crate::formula::MissingValue;
+use crate::mapping::{header_key, UnmappedHeader};
+use crate::File;
+
+/// How a single file (or a sheet of one) went through the merge
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileSummary {
+    pub file: String,
+    pub last_modified: String,
+    /// the rows under the header
+    pub rows_read: usize,
+    /// the rows cut from the top and the bottom of the file, footers included
+    pub rows_cut: usize,
+    pub rows_written: usize,
+    /// the columns whose header isn't the one the main file has in their place
+    pub header_mismatches: usize,
+    pub warnings: Vec<String>,
+}
+
+impl FileSummary {
+    /// start the summary of a file before anything is cut from it
+    pub fn new(file: &File, main_header: &[Cell]) -> Self {
+        FileSummary {
+            file: file.display_name().replace("-MAIN", ""),
+            last_modified: file.last_modified.clone(),
+            rows_read: file.rows.len(),
+            header_mismatches: header_mismatches(&file.header, main_header),
+            ..Default::default()
+        }
+    }
+
+    /// count what's left of the file once it's merged, along with what the merge reported on it
+    pub fn finish(
+        &mut self,
+        file: &File,
+        missing_values: &[MissingValue],
+        unmapped_headers: &[UnmappedHeader],
+        duplicates: &[DuplicateRow],
+    ) {
+        self.rows_written = file.rows.len();
+
+        if self.rows_read == 0 {
+            self.warnings.push("no rows under the header".to_string());
+        }
+
+        let missing = missing_values
+            .iter()
+            .filter(|value| value.file == file.name && value.sheet == file.sheet_name)
+            .count();
+        let unmapped = unmapped_headers
+            .iter()
+            .filter(|header| header.file == self.file)
+            .count();
+        let dropped = duplicates
+            .iter()
+            .filter(|duplicate| duplicate.file == self.file)
+            .count();
+
+        for (count, warning) in [
+            (missing, "formulas without a cached value"),
+            (unmapped, "headers not in the column mapping"),
+            (dropped, "duplicate rows dropped"),
+        ] {
+            if count > 0 {
+                self.warnings.push(format!("{}: {}", warning, count));
+            }
+        }
+    }
+}
+
+/// how many columns of a header aren't named like the main file's column in the same place,
+/// columns only one of them has count too
+pub fn header_mismatches(header: &[Cell], main_header: &[Cell]) -> usize {
+    let name = |header: &[Cell], column: usize| {
+        header
+            .get(column)
+            .map(|cell| header_key(&cell.as_text()))
+            .unwrap_or_default()
+    };
+
+    (0..header.len().max(main_header.len()))
+        .filter(|column| name(header, *column) != name(main_header, *column))
+        .count()
+}
+
+/// list how many rows each file had and what happened to them on a sheet of its own, with the
+/// totals of every file in the last row
+pub fn write_summary(workbook: &mut Workbook, summaries: &[FileSummary]) -> Result<()> {
+    let worksheet = workbook
+        .add_worksheet()
+        .set_name("Summary")
+        .context("error setting name of summary sheet")?;
+
+    let headers = [
+        "File Name",
+        "Date Modified",
+        "Rows Read",
+        "Rows Cut",
+        "Rows Written",
+        "Header Mismatches",
+        "Warnings",
+    ];
+    for (j, header) in headers.iter().enumerate() {
+        worksheet
+            .write_string(0, j as u16, *header)
+            .context("error writing header")?;
+    }
+
+    let total = FileSummary {
+        file: "Total".to_string(),
+        rows_read: summaries.iter().map(|summary| summary.rows_read).sum(),
+        rows_cut: summaries.iter().map(|summary| summary.rows_cut).sum(),
+        rows_written: summaries.iter().map(|summary| summary.rows_written).sum(),
+        header_mismatches: summaries
+            .iter()
+            .map(|summary| summary.header_mismatches)
+            .sum(),
+        warnings: summaries
+            .iter()
+            .flat_map(|summary| summary.warnings.clone())
+            .collect(),
+        ..Default::default()
+    };
+
+    for (i, summary) in summaries.iter().chain([&total]).enumerate() {
+        let row = (i + 1) as u32;
+        let counts = [
+            summary.rows_read,
+            summary.rows_cut,
+            summary.rows_written,
+            summary.header_mismatches,
+        ];
+
+        worksheet
+            .write_string(row, 0, &summary.file)
+            .context("error writing summary")?;
+        worksheet
+            .write_string(row, 1, &summary.last_modified)
+            .context("error writing summary")?;
+        for (j, count) in counts.iter().enumerate() {
+            worksheet
+                .write_number(row, (j + 2) as u16, *count as f64)
+                .context("error writing summary")?;
+        }
+
+        // the total is how many warnings there were, not all of them again
+        let warnings = if i == summaries.len() {
+            format!("warnings: {}", summary.warnings.len())
+        } else {
+            summary.warnings.join("; ")
+        };
+        worksheet
+            .write_string(row, 6, &warnings)
+            .context("error writing summary")?;
+    }
+
+    worksheet.autofit();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_file;
+
+    fn header(cells: &[&str]) -> Vec<Cell> {
+        cells.iter().map(|cell| Cell::from(*cell)).collect()
+    }
+
+    #[test]
+    fn test_header_mismatches() {
+        let main = header(&["Name", "City", "Qty"]);
+
+        assert_eq!(
+            header_mismatches(&header(&["name ", "City", "Qty"]), &main),
+            0
+        );
+        assert_eq!(
+            header_mismatches(&header(&["City", "Name", "Qty", "Notes"]), &main),
+            3
+        );
+    }
+
+    #[test]
+    fn test_row_counts() {
+        let main = header(&["Name", "City"]);
+        let mut file = test_file("b.xlsx", &["Name"], &[&["Ali"], &["Ali"], &["Total"]]);
+
+        let mut summary = FileSummary::new(&file, &main);
+        assert_eq!(summary.header_mismatches, 1);
+
+        file.cut_rows(0, 1);
+        summary.rows_cut = summary.rows_read - file.rows.len();
+        summary.finish(&file, &[], &[], &[]);
+
+        assert_eq!(
+            (summary.rows_read, summary.rows_cut, summary.rows_written),
+            (3, 1, 2)
+        );
+        assert!(summary.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let main = header(&["Name"]);
+        let mut file = test_file("b.xlsx", &["Name"], &[&["Ali"], &["Ali"]]);
+        let mut summary = FileSummary::new(&file, &main);
+
+        let duplicates = [DuplicateRow {
+            file: "b.xlsx".to_string(),
+            kept_from: "a.xlsx".to_string(),
+            row: vec!["Ali".into()],
+        }];
+        file.rows.pop();
+        summary.finish(&file, &[], &[], &duplicates);
+        assert_eq!(summary.warnings, vec!["duplicate rows dropped: 1"]);
+
+        let file = test_file("c.xlsx", &["Name"], &[]);
+        let mut summary = FileSummary::new(&file, &main);
+        summary.finish(&file, &[], &[], &[]);
+        assert_eq!(summary.warnings, vec!["no rows under the header"]);
+    }
+}
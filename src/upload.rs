@@ -0,0 +1,583 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Component, Path};
+
+use anyhow::Context;
+use axum::extract::multipart::Field;
+use calamine::{open_workbook_from_rs, Data, Dimensions, Range, Reader, Sheets};
+use size::Size;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, trace, warn};
+use zip::ZipArchive;
+
+use crate::delimited::read_delimited;
+use crate::error::{Error, Result};
+use crate::formula;
+use crate::manifest::FileOptions;
+use crate::sheet::{self, NamedSheet, SheetSelection};
+
+/// A sheet read from an upload, along with what's needed to process it besides its values
+#[derive(Debug)]
+pub struct SheetData {
+    pub name: String,
+    /// grown to cover the formulas too when they're read
+    pub range: Range<Data>,
+    /// only xlsx and xls have them
+    pub merged_regions: Vec<Dimensions>,
+    /// empty unless the formulas were asked for
+    pub formulas: Range<String>,
+}
+
+/// the first bytes of a zip archive (xlsx, xlsb, ods, or a plain archive of workbooks)
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// the first bytes of an OLE compound file (xls)
+const OLE_MAGIC: &[u8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+/// how much of a file is looked at to detect its format
+const SAMPLE_SIZE: u64 = 1024;
+
+/// The formats we can read, detected from the content rather than the MIME type the browser sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Xlsx,
+    Xlsb,
+    Xls,
+    Ods,
+    /// CSV or TSV
+    Delimited,
+    /// a zip of workbooks, possibly in folders, the spooler expands it into its entries
+    Archive,
+}
+
+impl Format {
+    /// look at the magic bytes first, then the extension, and only then check for plain text
+    pub fn detect<R: Read + Seek>(reader: &mut R, file_name: &str) -> io::Result<Option<Format>> {
+        let mut sample = vec![];
+        reader.by_ref().take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+        reader.rewind()?;
+
+        if sample.starts_with(OLE_MAGIC) {
+            return Ok(Some(Format::Xls));
+        }
+
+        if sample.starts_with(ZIP_MAGIC) {
+            if let Some(format) = Self::from_zip(reader) {
+                return Ok(Some(format));
+            }
+        }
+
+        if let Some(format) = Self::from_extension(file_name) {
+            return Ok(Some(format));
+        }
+
+        if is_text(&sample) {
+            return Ok(Some(Format::Delimited));
+        }
+
+        Ok(None)
+    }
+
+    /// tell the zip based formats apart from the entries they have, any other zip is an archive
+    fn from_zip<R: Read + Seek>(reader: &mut R) -> Option<Format> {
+        let archive = ZipArchive::new(reader).ok()?;
+
+        for name in archive.file_names() {
+            match name {
+                "xl/workbook.xml" => return Some(Format::Xlsx),
+                "xl/workbook.bin" => return Some(Format::Xlsb),
+                "content.xml" => return Some(Format::Ods),
+                _ => {}
+            }
+        }
+
+        Some(Format::Archive)
+    }
+
+    fn from_extension(file_name: &str) -> Option<Format> {
+        let ext = Path::new(file_name)
+            .extension()?
+            .to_string_lossy()
+            .to_ascii_lowercase();
+
+        match ext.as_str() {
+            "xlsx" | "xlsm" | "xlam" => Some(Format::Xlsx),
+            "xlsb" => Some(Format::Xlsb),
+            "xls" | "xla" => Some(Format::Xls),
+            "ods" => Some(Format::Ods),
+            "csv" | "tsv" | "txt" => Some(Format::Delimited),
+            _ => None,
+        }
+    }
+}
+
+/// text has no control characters besides whitespace, UTF-16 is let through since the CSV reader
+/// takes care of decoding it
+fn is_text(sample: &[u8]) -> bool {
+    if sample.is_empty() {
+        return false;
+    }
+
+    if sample.starts_with(b"\xFF\xFE") || sample.starts_with(b"\xFE\xFF") {
+        return true;
+    }
+
+    sample
+        .iter()
+        .filter(|b| **b != 0)
+        .all(|b| *b >= 0x20 || matches!(b, b'\t' | b'\n' | b'\r'))
+        && sample.iter().filter(|b| **b == 0).count() * 3 < sample.len()
+}
+
+/// only entries that look like workbooks are taken out of an archive, which leaves out the
+/// `__MACOSX` folders and hidden files archivers like to add
+fn is_workbook_entry(path: &Path) -> bool {
+    let hidden = path.components().any(|component| match component {
+        Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            name.starts_with('.') || name == "__MACOSX"
+        }
+        _ => false,
+    });
+
+    !hidden && Format::from_extension(&path.to_string_lossy()).is_some()
+}
+
+/// the same format the forms send their `last-mod[]` dates in
+fn format_zip_date(date: zip::DateTime) -> String {
+    format!(
+        "{:04}/{:02}/{:02} {:02}:{:02}",
+        date.year(),
+        date.month(),
+        date.day(),
+        date.hour(),
+        date.minute()
+    )
+}
+
+/// Upload size limits, in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct UploadLimits {
+    pub max_file_size: u64,
+    pub max_request_size: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            max_file_size: 200 * 1000 * 1000,
+            max_request_size: 800 * 1000 * 1000,
+        }
+    }
+}
+
+impl UploadLimits {
+    /// read the limits from `MAX_FILE_SIZE` and `MAX_REQUEST_SIZE`, anything missing or invalid
+    /// keeps its default
+    pub fn from_env() -> Self {
+        let default = UploadLimits::default();
+        let read = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+
+        UploadLimits {
+            max_file_size: read("MAX_FILE_SIZE", default.max_file_size),
+            max_request_size: read("MAX_REQUEST_SIZE", default.max_request_size),
+        }
+    }
+
+    /// the limit for the whole body, the spooler enforces the real limits so this leaves some
+    /// room for the other form fields and the multipart boundaries
+    pub fn body_limit(&self) -> usize {
+        (self.max_request_size + 10 * 1000 * 1000) as usize
+    }
+}
+
+/// Streams the files of a single request to temporary files, keeping count of the request limit
+pub struct Spooler {
+    limits: UploadLimits,
+    received: u64,
+}
+
+impl Spooler {
+    pub fn new(limits: UploadLimits) -> Self {
+        Spooler {
+            limits,
+            received: 0,
+        }
+    }
+
+    /// stream a file field to disk chunk by chunk, stopping as soon as a limit is exceeded
+    pub async fn spool(
+        &mut self,
+        mut field: Field<'_>,
+        field_name: String,
+        file_name: String,
+    ) -> Result<Upload> {
+        let temp = NamedTempFile::new().context("error creating a temporary file")?;
+        let mut writer =
+            tokio::fs::File::from_std(temp.reopen().context("error opening the temporary file")?);
+        let mut size: u64 = 0;
+
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .with_context(|| format!("error reading {:?}", file_name))?
+        {
+            size += chunk.len() as u64;
+            self.received += chunk.len() as u64;
+            self.check_limits(&file_name, size)?;
+
+            writer
+                .write_all(&chunk)
+                .await
+                .context("error writing to the temporary file")?;
+        }
+
+        writer
+            .flush()
+            .await
+            .context("error writing to the temporary file")?;
+        trace!("Spooled {:?} ({} bytes)", file_name, size);
+
+        Upload::new(field_name, file_name, temp, size)
+    }
+
+    /// `size` is how much of the current file was read so far
+    fn check_limits(&self, file_name: &str, size: u64) -> Result<()> {
+        if size > self.limits.max_file_size {
+            return Err(Error::FileTooLarge {
+                file: file_name.to_string(),
+                limit: Size::from_bytes(self.limits.max_file_size),
+            });
+        }
+
+        if self.received > self.limits.max_request_size {
+            return Err(Error::RequestTooLarge {
+                file: file_name.to_string(),
+                limit: Size::from_bytes(self.limits.max_request_size),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// replace the archives among the uploads with the workbooks in them, on a blocking thread
+    /// since the archives are read and written out there
+    pub async fn expand(
+        mut self,
+        uploads: Vec<Upload>,
+        options: Vec<FileOptions>,
+    ) -> Result<Vec<(Upload, FileOptions)>> {
+        tokio::task::spawn_blocking(move || self.expand_archives(uploads, options))
+            .await
+            .context("error expanding the archives")?
+    }
+
+    /// every entry of an archive takes its options, except for its size and, when none was sent,
+    /// its modified date
+    fn expand_archives(
+        &mut self,
+        uploads: Vec<Upload>,
+        options: Vec<FileOptions>,
+    ) -> Result<Vec<(Upload, FileOptions)>> {
+        let mut expanded = vec![];
+
+        for (upload, options) in uploads.into_iter().zip(options) {
+            if upload.format != Format::Archive {
+                expanded.push((upload, options));
+                continue;
+            }
+
+            let entries = self.extract(&upload)?;
+            if entries.is_empty() {
+                return Err(Error::EmptyArchive {
+                    file: upload.file_name,
+                });
+            }
+
+            for entry in entries {
+                let mut options = options.clone();
+                options.size = entry.size;
+                if options.last_mod.is_none() {
+                    options.last_mod = entry.last_modified.clone();
+                }
+
+                expanded.push((entry, options));
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    /// spool the workbooks of an archive to their own temporary files, they're named after their
+    /// path in the archive so files with the same name in different folders can be told apart
+    fn extract(&mut self, archive: &Upload) -> Result<Vec<Upload>> {
+        let mut zip = ZipArchive::new(archive.reader()?)
+            .with_context(|| format!("error reading archive {:?}", archive.file_name))?;
+        let mut uploads = vec![];
+
+        for i in 0..zip.len() {
+            let mut entry = zip
+                .by_index(i)
+                .with_context(|| format!("error reading archive {:?}", archive.file_name))?;
+
+            // entries with absolute paths or `..` in them are never written anywhere, skip them
+            let Some(path) = entry.enclosed_name() else {
+                warn!("Skipping {:?} in {:?}", entry.name(), archive.file_name);
+                continue;
+            };
+
+            if entry.is_dir() || !is_workbook_entry(&path) {
+                debug!("Skipping {:?} in {:?}", path, archive.file_name);
+                continue;
+            }
+
+            let file_name = path.to_string_lossy().replace('\\', "/");
+            self.check_limits(&file_name, entry.size())?;
+
+            // the size in the archive can't be trusted, so the limit is checked again on what
+            // was actually extracted
+            let mut temp = NamedTempFile::new().context("error creating a temporary file")?;
+            let size = io::copy(
+                &mut (&mut entry).take(self.limits.max_file_size + 1),
+                &mut temp,
+            )
+            .with_context(|| format!("error extracting {:?}", file_name))?;
+            self.received += size;
+            self.check_limits(&file_name, size)?;
+
+            let last_modified = entry.last_modified().map(format_zip_date);
+            trace!("Extracted {:?} ({} bytes)", file_name, size);
+
+            let mut upload = Upload::new(archive.field_name.clone(), file_name, temp, size)?;
+            upload.last_modified = last_modified;
+            uploads.push(upload);
+        }
+
+        Ok(uploads)
+    }
+}
+
+/// A workbook received from a multipart form. It's spooled to a temporary file and kept around
+/// until the rest of the form fields (dates, sheets, ...) are read, since they usually come after
+/// the files
+#[derive(Debug)]
+pub struct Upload {
+    /// the name of the form field, e.g. `main-file` or `excel-file[]`
+    pub field_name: String,
+    pub file_name: String,
+    pub format: Format,
+    /// the size of the file in bytes
+    pub size: u64,
+    /// the modified date of a file that came out of an archive
+    pub last_modified: Option<String>,
+    /// removed from the disk once the upload is dropped
+    file: NamedTempFile,
+}
+
+impl Upload {
+    /// detect the format of the file, anything we can't read is an error instead of being dropped
+    pub fn new(
+        field_name: String,
+        file_name: String,
+        file: NamedTempFile,
+        size: u64,
+    ) -> Result<Self> {
+        let mut reader = BufReader::new(file.reopen().context("error opening the temporary file")?);
+        let format = Format::detect(&mut reader, &file_name)
+            .with_context(|| format!("error reading {:?}", file_name))?
+            .ok_or_else(|| Error::UnsupportedFormat {
+                file: file_name.clone(),
+            })?;
+        debug!("Format of {:?}: {:?}", file_name, format);
+
+        Ok(Upload {
+            field_name,
+            file_name,
+            format,
+            size,
+            last_modified: None,
+            file,
+        })
+    }
+
+    /// a new reader from the start of the spooled file
+    pub fn reader(&self) -> Result<BufReader<File>> {
+        let file = self
+            .file
+            .reopen()
+            .with_context(|| format!("error opening {:?}", self.file_name))?;
+
+        Ok(BufReader::new(file))
+    }
+
+    /// the whole content of the spooled file
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.reader()?
+            .read_to_end(&mut bytes)
+            .with_context(|| format!("error reading {:?}", self.file_name))?;
+
+        Ok(bytes)
+    }
+
+    pub fn is_main(&self) -> bool {
+        self.field_name == "main-file"
+    }
+
+    pub fn is_delimited(&self) -> bool {
+        self.format == Format::Delimited
+    }
+
+    /// the name of the single sheet of a CSV/TSV file
+    pub fn delimited_sheet_name(&self) -> String {
+        Path::new(&self.file_name)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// open the upload as a workbook of the detected format
+    pub fn open_workbook(&self) -> Result<Sheets<BufReader<File>>> {
+        let reader = self.reader()?;
+        let workbook = match self.format {
+            Format::Xlsx => open_workbook_from_rs(reader)
+                .map(Sheets::Xlsx)
+                .map_err(Into::into),
+            Format::Xlsb => open_workbook_from_rs(reader)
+                .map(Sheets::Xlsb)
+                .map_err(Into::into),
+            Format::Xls => open_workbook_from_rs(reader)
+                .map(Sheets::Xls)
+                .map_err(Into::into),
+            Format::Ods => open_workbook_from_rs(reader)
+                .map(Sheets::Ods)
+                .map_err(Into::into),
+            Format::Delimited => Err(calamine::Error::Msg("a CSV/TSV file is not a workbook")),
+            Format::Archive => Err(calamine::Error::Msg("a zip archive is not a workbook")),
+        }
+        .with_context(|| format!("error opening workbook {:?}", self.file_name))?;
+
+        Ok(workbook)
+    }
+
+    /// read the selected sheets, along with how many sheets the upload has in total
+    pub fn read_sheets(&self, selection: &SheetSelection) -> Result<(usize, Vec<NamedSheet>)> {
+        if self.is_delimited() {
+            // a delimited file is a workbook with a single sheet
+            let sheet_name = self.delimited_sheet_name();
+            selection.resolve(&self.file_name, std::slice::from_ref(&sheet_name))?;
+
+            // the whole text is needed to sniff the encoding and the delimiter
+            let range = read_delimited(&self.bytes()?, &self.file_name)?;
+
+            return Ok((1, vec![(sheet_name, range)]));
+        }
+
+        let mut workbook = self.open_workbook()?;
+        let sheet_count = workbook.sheet_names().len();
+        let sheets = sheet::read_sheets(&mut workbook, &self.file_name, selection)?;
+
+        Ok((sheet_count, sheets))
+    }
+
+    /// like `read_sheets`, but with the merged regions of every sheet and, if asked for, their
+    /// formulas
+    pub fn read_sheet_data(
+        &self,
+        selection: &SheetSelection,
+        formulas: bool,
+    ) -> Result<(usize, Vec<SheetData>)> {
+        if self.is_delimited() {
+            let (sheet_count, sheets) = self.read_sheets(selection)?;
+            let sheets = sheets
+                .into_iter()
+                .map(|(name, range)| SheetData {
+                    name,
+                    range,
+                    merged_regions: vec![],
+                    formulas: Range::empty(),
+                })
+                .collect();
+
+            return Ok((sheet_count, sheets));
+        }
+
+        let mut workbook = self.open_workbook()?;
+        let sheet_count = workbook.sheet_names().len();
+        let sheet_names = selection.resolve(&self.file_name, &workbook.sheet_names())?;
+
+        if let Sheets::Xlsx(xlsx) = &mut workbook {
+            if let Err(e) = xlsx.load_merged_regions() {
+                warn!("No merged regions for {:?}: {:?}", self.file_name, e);
+            }
+        }
+
+        let mut sheets = vec![];
+        for sheet_name in sheet_names {
+            let merged_regions = match &workbook {
+                Sheets::Xlsx(xlsx) => xlsx
+                    .merged_regions()
+                    .iter()
+                    .filter(|region| region.0 == sheet_name)
+                    .map(|region| region.2)
+                    .collect(),
+                Sheets::Xls(xls) => xls.worksheet_merge_cells(&sheet_name).unwrap_or_default(),
+                Sheets::Xlsb(_) | Sheets::Ods(_) => vec![],
+            };
+            trace!("Merged regions: {:?}", merged_regions);
+
+            let range = match workbook.worksheet_range(&sheet_name) {
+                Ok(range) => range,
+                Err(e) => {
+                    warn!("Skipping sheet {:?}: {:?}", sheet_name, e);
+                    continue;
+                }
+            };
+
+            let formulas = match &mut workbook {
+                // ods formulas are in a syntax of their own, excel can't read them back
+                Sheets::Ods(_) => Range::empty(),
+                _ if !formulas => Range::empty(),
+                workbook => workbook.worksheet_formula(&sheet_name).unwrap_or_else(|e| {
+                    warn!("No formulas for {:?}: {:?}", sheet_name, e);
+                    Range::empty()
+                }),
+            };
+
+            sheets.push(SheetData {
+                range: formula::cover(range, &formulas),
+                name: sheet_name,
+                merged_regions,
+                formulas,
+            });
+        }
+
+        Ok((sheet_count, sheets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        let detect = |bytes: &[u8], file_name| {
+            Format::detect(&mut std::io::Cursor::new(bytes), file_name).unwrap()
+        };
+
+        assert_eq!(detect(OLE_MAGIC, "a.bin"), Some(Format::Xls));
+        assert_eq!(detect(b"a,b\n1,2", "upload"), Some(Format::Delimited));
+        assert_eq!(detect(b"\x00\x01\x02", "a.ods"), Some(Format::Ods));
+        assert_eq!(detect(b"\x00\x01\x02", "upload"), None);
+
+        assert!(is_workbook_entry(Path::new("2023/march/a.xlsx")));
+        assert!(!is_workbook_entry(Path::new("__MACOSX/2023/._a.xlsx")));
+        assert!(!is_workbook_entry(Path::new("2023/Thumbs.db")));
+    }
+}